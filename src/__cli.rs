@@ -0,0 +1,21 @@
+//! Helpers of the `busylib-crypt` binary, not part of the supported API.
+
+use crate::errors::ConfigError;
+
+/// Upper bound of the bytes [`stream_key_id`] needs
+pub const STREAM_HEADER_MAX: usize = crate::crypto::STREAM_HEADER_MAX;
+
+/// The id of the key a stream was encrypted with, read from its first
+/// [`STREAM_HEADER_MAX`] bytes
+pub fn stream_key_id(header: &[u8]) -> Option<String> {
+    crate::crypto::stream_key_id(header)
+}
+
+/// The `.env` file content `content` with the values of `keys` wrapped in `ENC(...)` markers
+/// around what `encrypt` returns for them, failing if one of `keys` is not in the file
+pub fn encrypt_keys<F>(content: &str, keys: &[&str], encrypt: F) -> Result<String, ConfigError>
+where
+    F: Fn(&str) -> String,
+{
+    crate::config::envfile::encrypt_keys(content, keys, encrypt)
+}
@@ -0,0 +1,419 @@
+//! Encrypt and decrypt configuration values for `ENC(...)` markers.
+//!
+//! ```text
+//! busylib-crypt encrypt 's3cret'                          # prints ENC(...)
+//! printf 's3cret\n' | busylib-crypt --key-file master.key encrypt --raw
+//! busylib-crypt decrypt 'ENC(...)'
+//! busylib-crypt env .env --keys DB_PASSWORD,API_TOKEN --in-place
+//! busylib-crypt encrypt-file dump.sql dump.sql.enc
+//! ```
+//!
+//! The key is read from the variable named by `--key-env` (`BUSYLIB_MASTER_KEY` by default),
+//! then from `--key-file` (`BUSYLIB_MASTER_KEY_FILE` by default), and prompted for otherwise,
+//! the same precedence as `busylib::config::secret::master_key`.
+
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use busylib::__cli::{encrypt_keys, stream_key_id, STREAM_HEADER_MAX};
+use busylib::config::secret::{is_encrypted, MASTER_KEY_FILE_VAR, MASTER_KEY_VAR};
+use busylib::crypto::{decrypt_file, encrypt_file, KeyDerivation, Keyring};
+use clap::{Parser, Subcommand, ValueEnum};
+
+#[derive(Parser)]
+#[command(
+    name = "busylib-crypt",
+    version,
+    about = "Encrypt and decrypt busylib config values"
+)]
+struct Cli {
+    /// Read the key from this environment variable
+    #[arg(long, global = true, default_value = MASTER_KEY_VAR)]
+    key_env: String,
+    /// Read the key from this file when the --key-env variable is unset
+    #[arg(long, global = true, env = MASTER_KEY_FILE_VAR)]
+    key_file: Option<PathBuf>,
+    /// Key id recorded in new ciphertexts, to match a key of the application's keyring
+    #[arg(long, global = true, default_value = "")]
+    key_id: String,
+    /// Key derivation for new ciphertexts
+    #[arg(long, global = true, value_enum, default_value_t = Kdf::Argon2id)]
+    kdf: Kdf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Encrypt values given as arguments, the content of --file, or stdin lines
+    Encrypt {
+        values: Vec<String>,
+        /// Encrypt the content of this file as one value
+        #[arg(long, conflicts_with = "values")]
+        file: Option<PathBuf>,
+        /// Print bare base64 instead of ENC(...)
+        #[arg(long)]
+        raw: bool,
+    },
+    /// Decrypt values given as arguments, the content of --file, or stdin lines, with or
+    /// without ENC(...)
+    Decrypt {
+        values: Vec<String>,
+        /// Decrypt the content of this file as one value
+        #[arg(long, conflicts_with = "values")]
+        file: Option<PathBuf>,
+    },
+    /// Wrap the values of chosen keys of a .env file in ENC(...), printing the result
+    Env {
+        path: PathBuf,
+        /// Comma separated names of the variables to encrypt
+        #[arg(long, required = true, value_delimiter = ',')]
+        keys: Vec<String>,
+        /// Write the result to this file instead of stdout
+        #[arg(long, conflicts_with = "in_place")]
+        output: Option<PathBuf>,
+        /// Rewrite the .env file itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Encrypt a file of any size in authenticated chunks
+    EncryptFile { src: PathBuf, dst: PathBuf },
+    /// Decrypt a file written by encrypt-file
+    DecryptFile { src: PathBuf, dst: PathBuf },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Kdf {
+    /// Argon2id with 19 MiB and 2 iterations
+    Argon2id,
+    /// PBKDF2-HMAC-SHA256 with 600 000 iterations
+    Pbkdf2,
+    /// A single SHA-256, only for random keys
+    Sha256,
+}
+
+impl From<Kdf> for KeyDerivation {
+    fn from(kdf: Kdf) -> Self {
+        match kdf {
+            Kdf::Argon2id => KeyDerivation::argon2id(),
+            Kdf::Pbkdf2 => KeyDerivation::pbkdf2(),
+            Kdf::Sha256 => KeyDerivation::Sha256,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("busylib-crypt: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let encrypting = !matches!(
+        cli.command,
+        Command::Decrypt { .. } | Command::DecryptFile { .. }
+    );
+    let key = read_key(
+        cli.key_file.as_deref(),
+        std::env::var(&cli.key_env).ok(),
+        encrypting,
+        |prompt| rpassword::prompt_password(prompt),
+    )?;
+    let keyring = Keyring::new(&cli.key_id, &key).key_derivation(cli.kdf.into())?;
+    match cli.command {
+        Command::Encrypt { values, file, raw } => {
+            for value in inputs(values, file, io::stdin().lock())? {
+                let encrypted = keyring.encrypt(&value);
+                if raw {
+                    println!("{}", encrypted);
+                } else {
+                    println!("ENC({})", encrypted);
+                }
+            }
+        }
+        Command::Decrypt { values, file } => {
+            for value in inputs(values, file, io::stdin().lock())? {
+                println!("{}", decrypt(&value, &key)?);
+            }
+        }
+        Command::Env {
+            path,
+            keys,
+            output,
+            in_place,
+        } => {
+            let content = fs::read_to_string(&path)
+                .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+            let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+            let rewritten = encrypt_keys(&content, &keys, |value| keyring.encrypt(value))?;
+            match (output, in_place) {
+                (Some(output), _) => write(&output, &rewritten, None)?,
+                (None, true) => write(&path, &rewritten, Some(&path))?,
+                (None, false) => print!("{}", rewritten),
+            }
+        }
+        Command::EncryptFile { src, dst } => {
+            encrypt_file(&src, &dst, &keyring).await?;
+        }
+        Command::DecryptFile { src, dst } => {
+            let id = read_stream_key_id(&src).unwrap_or_default();
+            decrypt_file(&src, &dst, &Keyring::new(&id, &key)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// the value of --key-env, the key from --key-file or `prompt`, asked twice when encrypting
+fn read_key<P>(
+    key_file: Option<&Path>,
+    env_key: Option<String>,
+    encrypting: bool,
+    mut prompt: P,
+) -> Result<String, Box<dyn Error>>
+where
+    P: FnMut(&str) -> io::Result<String>,
+{
+    if let Some(key) = env_key.filter(|k| !k.is_empty()) {
+        return Ok(key);
+    }
+    if let Some(path) = key_file {
+        let key = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read key file {}: {}", path.display(), e))?;
+        let key = key.trim_end_matches(['\r', '\n']);
+        if key.is_empty() {
+            return Err(format!("key file {} is empty", path.display()).into());
+        }
+        return Ok(key.to_string());
+    }
+    let key = prompt("Key: ")?;
+    if encrypting && prompt("Repeat key: ")? != key {
+        return Err("keys do not match".into());
+    }
+    if key.is_empty() {
+        return Err("empty key".into());
+    }
+    Ok(key)
+}
+
+/// values from arguments, a file, or non-empty lines of `stdin`
+fn inputs(
+    values: Vec<String>,
+    file: Option<PathBuf>,
+    stdin: impl BufRead,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    if let Some(path) = file {
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        return Ok(vec![content.trim_end_matches(['\r', '\n']).to_string()]);
+    }
+    if !values.is_empty() {
+        return Ok(values);
+    }
+    let mut lines = Vec::new();
+    for line in stdin.lines() {
+        let line = line?;
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    Ok(lines)
+}
+
+/// decrypt a value with or without ENC(...) with `key`, whatever key id it was encrypted with
+fn decrypt(value: &str, key: &str) -> Result<String, Box<dyn Error>> {
+    let value = value.trim();
+    let value = if is_encrypted(value) {
+        &value[4..value.len() - 1]
+    } else {
+        value
+    };
+    let id = Keyring::key_id(value).unwrap_or_default();
+    Ok(Keyring::new(&id, key).decrypt(value)?)
+}
+
+/// write through a temporary file that is readable by the owner only, or has the permissions
+/// of `like`, before anything is written to it
+fn write(path: &Path, content: &str, like: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let tmp = path.with_file_name(name);
+    // a leftover of a crash may have looser permissions
+    let _ = fs::remove_file(&tmp);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    if let Some(like) = like {
+        file.set_permissions(fs::metadata(like)?.permissions())?;
+    }
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// the key id in the header of a file written by encrypt-file
+fn read_stream_key_id(path: &Path) -> Option<String> {
+    let mut header = Vec::with_capacity(STREAM_HEADER_MAX);
+    fs::File::open(path)
+        .ok()?
+        .take(STREAM_HEADER_MAX as u64)
+        .read_to_end(&mut header)
+        .ok()?;
+    stream_key_id(&header)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::io;
+
+    use busylib::crypto::{KeyDerivation, Keyring};
+    use clap::Parser;
+
+    use crate::{decrypt, inputs, read_key, write, Cli, Command};
+
+    fn no_prompt(_: &str) -> io::Result<String> {
+        panic!("the key should not be prompted for")
+    }
+
+    #[test]
+    fn key_sources() {
+        let dir = env::temp_dir().join("busylib_crypt_bin_test");
+        fs::create_dir_all(&dir).unwrap();
+        let key_file = dir.join("master.key");
+        fs::write(&key_file, "from file\n").unwrap();
+
+        // the variable wins over the file, like master_key()
+        let key = read_key(Some(&key_file), Some("from env".into()), true, no_prompt);
+        assert_eq!(key.unwrap(), "from env");
+        let key = read_key(Some(&key_file), Some(String::new()), true, no_prompt);
+        assert_eq!(key.unwrap(), "from file");
+        let key = read_key(None, Some("from env".into()), true, no_prompt);
+        assert_eq!(key.unwrap(), "from env");
+        let err = read_key(Some(&dir.join("missing.key")), None, false, no_prompt).unwrap_err();
+        assert!(err.to_string().contains("missing.key"));
+        let empty_file = dir.join("empty.key");
+        fs::write(&empty_file, "\n").unwrap();
+        let err = read_key(Some(&empty_file), None, true, no_prompt).unwrap_err();
+        assert!(err.to_string().contains("is empty"));
+
+        // prompted once when decrypting, twice when encrypting
+        let mut prompts = Vec::new();
+        let mut answer = |prompt: &str| {
+            prompts.push(prompt.to_string());
+            Ok("typed".to_string())
+        };
+        assert_eq!(
+            read_key(None, Some(String::new()), false, &mut answer).unwrap(),
+            "typed"
+        );
+        assert_eq!(read_key(None, None, true, &mut answer).unwrap(), "typed");
+        assert_eq!(prompts, vec!["Key: ", "Key: ", "Repeat key: "]);
+
+        let mut answers = vec!["one", "two"].into_iter();
+        let err = read_key(None, None, true, |_| Ok(answers.next().unwrap().into())).unwrap_err();
+        assert_eq!(err.to_string(), "keys do not match");
+        let err = read_key(None, None, false, |_| Ok(String::new())).unwrap_err();
+        assert_eq!(err.to_string(), "empty key");
+    }
+
+    #[test]
+    fn input_sources() {
+        let values = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(inputs(values.clone(), None, &b"c\n"[..]).unwrap(), values);
+        let lines = inputs(Vec::new(), None, &b"c\n\nd\r\n"[..]).unwrap();
+        assert_eq!(lines, vec!["c", "d"]);
+
+        let dir = env::temp_dir().join("busylib_crypt_bin_test");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("value.pem");
+        fs::write(&file, "line 1\n\nline 2\n").unwrap();
+        let value = inputs(Vec::new(), Some(file), &b""[..]).unwrap();
+        assert_eq!(value, vec!["line 1\n\nline 2"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join("busylib_crypt_bin_test");
+        fs::create_dir_all(&dir).unwrap();
+        let mode =
+            |path: &std::path::Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let output = dir.join("output.env");
+        let _ = fs::remove_file(&output);
+        write(&output, "A=ENC(x)\n", None).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "A=ENC(x)\n");
+        assert_eq!(mode(&output), 0o600);
+
+        let in_place = dir.join("in_place.env");
+        fs::write(&in_place, "A=x\n").unwrap();
+        fs::set_permissions(&in_place, fs::Permissions::from_mode(0o640)).unwrap();
+        write(&in_place, "A=ENC(x)\n", Some(&in_place)).unwrap();
+        assert_eq!(mode(&in_place), 0o640);
+    }
+
+    #[test]
+    fn decrypt_any_key_id() {
+        let keyring = Keyring::new("tenant-42", "master")
+            .key_derivation(KeyDerivation::Pbkdf2 { iterations: 1000 })
+            .unwrap();
+        let encrypted = keyring.encrypt("s3cret");
+        assert_eq!(decrypt(&encrypted, "master").unwrap(), "s3cret");
+        let marked = format!(" ENC({})\n", encrypted);
+        assert_eq!(decrypt(&marked, "master").unwrap(), "s3cret");
+        assert!(decrypt(&encrypted, "other").is_err());
+    }
+
+    #[test]
+    fn parse_arguments() {
+        let cli = Cli::try_parse_from([
+            "busylib-crypt",
+            "env",
+            ".env",
+            "--keys",
+            "DB_PASSWORD,API_TOKEN",
+            "--in-place",
+            "--key-id",
+            "2024",
+        ])
+        .unwrap();
+        assert_eq!(cli.key_id, "2024");
+        assert!(matches!(
+            cli.command,
+            Command::Env { keys, in_place: true, .. } if keys == ["DB_PASSWORD", "API_TOKEN"]
+        ));
+
+        for args in [
+            &["busylib-crypt", "encrypt", "value", "--file", "value.txt"][..],
+            &[
+                "busylib-crypt",
+                "env",
+                ".env",
+                "--keys",
+                "A",
+                "--in-place",
+                "--output",
+                "o",
+            ],
+            &["busylib-crypt", "env", ".env"],
+            &["busylib-crypt", "--kdf", "scrypt", "encrypt"],
+        ] {
+            assert!(Cli::try_parse_from(args).is_err(), "{:?}", args);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::config::Source;
+
+const MASK: &str = "******";
+
+static SECRET_PATTERNS: Lazy<ArcSwap<Vec<String>>> = Lazy::new(|| {
+    ArcSwap::from_pointee(
+        [
+            "PASSWORD",
+            "PASSWD",
+            "SECRET",
+            "TOKEN",
+            "KEY",
+            "PRIVATE",
+            "CREDENTIAL",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect(),
+    )
+});
+
+static READS: Lazy<Mutex<BTreeMap<String, Read>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+struct Read {
+    value: Option<String>,
+    source: Source,
+    decrypted: bool,
+}
+
+/// One key read through the config module, as reported by [`effective_config`]
+#[derive(Clone, Debug, Serialize)]
+pub struct ConfigEntry {
+    pub key: String,
+    /// masked when `redacted`, `None` when a typed default was used
+    pub value: Option<String>,
+    pub source: Source,
+    /// whether the key was missing and the default was used
+    pub defaulted: bool,
+    pub redacted: bool,
+}
+
+impl fmt::Display for ConfigEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} = {} ({})",
+            self.key,
+            self.value.as_deref().unwrap_or("<default>"),
+            self.source
+        )
+    }
+}
+
+/// Add a case-insensitive name pattern marking values as secret, in addition to
+/// `PASSWORD`, `PASSWD`, `SECRET`, `TOKEN`, `KEY`, `PRIVATE` and `CREDENTIAL`
+pub fn add_secret_pattern(pattern: &str) {
+    let mut patterns = SECRET_PATTERNS.load().as_ref().clone();
+    patterns.push(pattern.to_uppercase());
+    SECRET_PATTERNS.store(patterns.into());
+}
+
+/// whether the value of `name` must be masked in logs and dumps
+pub fn is_secret(name: &str) -> bool {
+    let name = name.to_uppercase();
+    SECRET_PATTERNS
+        .load()
+        .iter()
+        .any(|p| name.contains(p.as_str()))
+}
+
+/// `value`, or a mask if `name` is secret
+pub fn redact<'a>(name: &str, value: &'a str) -> &'a str {
+    if is_secret(name) {
+        MASK
+    } else {
+        value
+    }
+}
+
+/// `value`, or a mask if it is `secret` or `name` is secret
+pub(crate) fn mask<'a>(name: &str, value: &'a str, secret: bool) -> &'a str {
+    if secret {
+        MASK
+    } else {
+        redact(name, value)
+    }
+}
+
+/// whether the last recorded value of `name` was decrypted or read from a secret file
+pub(crate) fn was_secret(name: &str) -> bool {
+    let Ok(reads) = READS.lock() else {
+        return false;
+    };
+    reads
+        .get(name)
+        .is_some_and(|read| read.decrypted || matches!(read.source, Source::SecretFile(_)))
+}
+
+pub(crate) fn record(name: &str, value: Option<&str>, source: Source, decrypted: bool) {
+    let read = Read {
+        value: value.map(|v| v.to_string()),
+        source,
+        decrypted,
+    };
+    if let Ok(mut reads) = READS.lock() {
+        reads.insert(name.to_string(), read);
+    }
+}
+
+/// Every key read so far with its latest value and source. Values whose name is secret, that
+/// were decrypted or read from a secret file are masked.
+pub fn effective_config() -> Vec<ConfigEntry> {
+    let Ok(reads) = READS.lock() else {
+        return Vec::new();
+    };
+    reads
+        .iter()
+        .map(|(key, read)| {
+            let redacted =
+                read.decrypted || is_secret(key) || matches!(read.source, Source::SecretFile(_));
+            ConfigEntry {
+                key: key.clone(),
+                value: match &read.value {
+                    Some(_) if redacted => Some(MASK.to_string()),
+                    value => value.clone(),
+                },
+                source: read.source.clone(),
+                defaulted: read.source == Source::Default,
+                redacted,
+            }
+        })
+        .collect()
+}
+
+/// [`effective_config`] as one `key = value (source)` line per key
+pub fn dump() -> String {
+    effective_config()
+        .iter()
+        .map(|entry| format!("{}\n", entry))
+        .collect()
+}
+
+/// Emit [`effective_config`] as a single structured `info` event, typically at startup
+pub fn log_effective_config() {
+    let entries = effective_config();
+    let config = serde_json::to_string(&entries).unwrap_or_default();
+    tracing::info!(
+        target: "busylib::config",
+        keys = entries.len(),
+        config = %config,
+        "effective configuration"
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::dump::{effective_config, is_secret};
+    use crate::config::secret::{encrypt_value, MASTER_KEY_VAR};
+    use crate::config::{env_parse, env_string_with_default, EnvOverrides, Source};
+
+    #[test]
+    fn dump_redacts_secrets() {
+        let _guard = EnvOverrides::new()
+            .set(MASTER_KEY_VAR, "master")
+            .set("DUMP_TEST_HOST", "db.internal")
+            .set("DUMP_TEST_DB_PASSWORD", "s3cret")
+            .set("DUMP_TEST_DSN", &encrypt_value("postgres://", "master"))
+            .enter();
+
+        env_string_with_default("DUMP_TEST_HOST", "localhost");
+        env_string_with_default("DUMP_TEST_DB_PASSWORD", "");
+        env_string_with_default("DUMP_TEST_DSN", "");
+        env_string_with_default("DUMP_TEST_USER", "admin");
+        env_parse("DUMP_TEST_POOL", 4u32).unwrap();
+
+        let entries = effective_config();
+        let entry = |key: &str| entries.iter().find(|e| e.key == key).unwrap();
+        assert_eq!(
+            entry("DUMP_TEST_HOST").value.as_deref(),
+            Some("db.internal")
+        );
+        assert_eq!(entry("DUMP_TEST_HOST").source, Source::Override);
+        assert!(entry("DUMP_TEST_DB_PASSWORD").redacted);
+        assert_eq!(
+            entry("DUMP_TEST_DB_PASSWORD").value.as_deref(),
+            Some("******")
+        );
+        assert!(entry("DUMP_TEST_DSN").redacted);
+        assert_eq!(entry("DUMP_TEST_USER").value.as_deref(), Some("admin"));
+        assert!(entry("DUMP_TEST_USER").defaulted);
+        assert_eq!(entry("DUMP_TEST_POOL").value, None);
+        assert!(entry("DUMP_TEST_POOL").defaulted);
+
+        assert!(is_secret("db.api_key"));
+        assert!(!is_secret("db.host"));
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+
+use crate::config::{overrides, secret};
+use crate::errors::ConfigError;
+
+/// What loading did with one variable of one file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvAction {
+    /// the variable was not set before
+    Set,
+    /// the variable replaced a process env value or the value of an earlier file
+    Overridden,
+    /// the process env value was kept
+    Skipped,
+}
+
+#[derive(Clone, Debug)]
+pub struct EnvEntry {
+    pub key: String,
+    pub file: PathBuf,
+    pub action: EnvAction,
+}
+
+/// Files loaded by [`EnvFiles::load`] and what happened to every variable
+#[derive(Clone, Debug, Default)]
+pub struct EnvReport {
+    pub files: Vec<PathBuf>,
+    pub entries: Vec<EnvEntry>,
+}
+
+impl EnvReport {
+    pub fn keys(&self, action: EnvAction) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|e| e.action == action)
+            .map(|e| e.key.as_str())
+            .collect()
+    }
+}
+
+/// Loads a list of `.env` files into the process env.
+///
+/// Later files override earlier ones. Variables already set in the process env, or in an
+/// [`crate::config::EnvOverrides`] scope, are kept unless [`EnvFiles::override_existing`] is
+/// enabled. Missing files are skipped.
+///
+/// Values may reference other variables as `$VAR`, `${VAR}`, `${VAR:-default}` (default when
+/// unset or empty) or `${VAR-default}` (default when unset), resolved against the values loaded
+/// so far and then the process env. Single-quoted values are taken literally, `\$` escapes `$`.
+/// Nothing is exported if any file fails to parse.
+///
+/// ```rust,ignore
+/// let report = EnvFiles::new().file(".env").file(".env.local").load()?;
+/// info!("overridden: {:?}", report.keys(EnvAction::Overridden));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct EnvFiles {
+    files: Vec<PathBuf>,
+    override_existing: bool,
+}
+
+impl EnvFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.files.push(path.into());
+        self
+    }
+
+    /// Whether file values replace variables already set in the process env, disabled by default
+    pub fn override_existing(mut self, enabled: bool) -> Self {
+        self.override_existing = enabled;
+        self
+    }
+
+    pub fn load(&self) -> Result<EnvReport, ConfigError> {
+        let mut report = EnvReport::default();
+        let mut values: HashMap<String, String> = HashMap::new();
+        for path in self.files.iter().filter(|p| p.exists()) {
+            let content = read(path)?;
+            for (i, line) in content.lines().enumerate() {
+                let lookup =
+                    |name: &str| values.get(name).cloned().or_else(|| overrides::var(name));
+                let Some((key, value)) = parse_line(line, lookup).map_err(|e| ConfigError {
+                    details: format!("{}:{}: {}", path.display(), i + 1, e),
+                })?
+                else {
+                    continue;
+                };
+                let action = if values.contains_key(&key) {
+                    EnvAction::Overridden
+                } else if overrides::var(&key).is_none() {
+                    EnvAction::Set
+                } else if self.override_existing {
+                    EnvAction::Overridden
+                } else {
+                    EnvAction::Skipped
+                };
+                if action != EnvAction::Skipped {
+                    values.insert(key.clone(), value);
+                }
+                report.entries.push(EnvEntry {
+                    key,
+                    file: path.clone(),
+                    action,
+                });
+            }
+            report.files.push(path.clone());
+        }
+        for (key, value) in values {
+            env::set_var(key, value);
+        }
+        Ok(report)
+    }
+}
+
+/// Parse a `.env` file without exporting it, references are resolved against earlier lines
+/// and the process env
+pub fn parse_file(path: &Path) -> Result<HashMap<String, String>, ConfigError> {
+    let content = read(path)?;
+    let mut values: HashMap<String, String> = HashMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let lookup = |name: &str| values.get(name).cloned().or_else(|| overrides::var(name));
+        if let Some((key, value)) = parse_line(line, lookup).map_err(|e| ConfigError {
+            details: format!("{}:{}: {}", path.display(), i + 1, e),
+        })? {
+            values.insert(key, value);
+        }
+    }
+    Ok(values)
+}
+
+/// The first `.env` file in the current directory and its parents, the file
+/// [`crate::config::dotenv`] loads and [`crate::config::ConfigLoader`] reads
+pub(crate) fn find_dotenv() -> io::Result<Option<PathBuf>> {
+    Ok(env::current_dir()?
+        .ancestors()
+        .map(|dir| dir.join(".env"))
+        .find(|path| path.is_file()))
+}
+
+/// Rewrite the `.env` file content `content` with the values of `keys` replaced by
+/// `ENC(...)` markers around what `encrypt` returns for them, e.g. `|v| keyring.encrypt(v)`.
+/// References are expanded before encrypting. Other lines are kept as they are, values that are
+/// already encrypted are left alone and comments after a rewritten value are dropped.
+/// Fails if one of `keys` is not in the file, so that a typo does not leave a secret in clear.
+pub(crate) fn encrypt_keys<F>(
+    content: &str,
+    keys: &[&str],
+    encrypt: F,
+) -> Result<String, ConfigError>
+where
+    F: Fn(&str) -> String,
+{
+    let mut values: HashMap<String, String> = HashMap::new();
+    let mut lines = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let lookup = |name: &str| values.get(name).cloned().or_else(|| overrides::var(name));
+        let parsed = parse_line(line, lookup).map_err(|e| ConfigError {
+            details: format!("line {}: {}", i + 1, e),
+        })?;
+        let Some((key, value)) = parsed else {
+            lines.push(line.to_string());
+            continue;
+        };
+        if keys.contains(&key.as_str()) && !secret::is_encrypted(&value) {
+            let (name, _) = line.split_once('=').unwrap_or((line, ""));
+            lines.push(format!("{}=ENC({})", name.trim_end(), encrypt(&value)));
+        } else {
+            lines.push(line.to_string());
+        }
+        values.insert(key, value);
+    }
+    let missing: Vec<&str> = keys
+        .iter()
+        .filter(|key| !values.contains_key(**key))
+        .copied()
+        .collect();
+    if !missing.is_empty() {
+        return Err(ConfigError {
+            details: format!("not in the file: {}", missing.join(", ")),
+        });
+    }
+    let mut rewritten = lines.join("\n");
+    if content.ends_with('\n') {
+        rewritten.push('\n');
+    }
+    Ok(rewritten)
+}
+
+fn read(path: &Path) -> Result<String, ConfigError> {
+    fs::read_to_string(path).map_err(|e| ConfigError {
+        details: format!("failed to read {}: {}", path.display(), e),
+    })
+}
+
+fn parse_line<F>(line: &str, lookup: F) -> Result<Option<(String, String)>, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let line = line
+        .strip_prefix("export ")
+        .map(str::trim_start)
+        .unwrap_or(line);
+    let (key, value) = line.split_once('=').ok_or("expected `KEY=VALUE`")?;
+    let key = key.trim();
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+    {
+        return Err(format!("invalid variable name {:?}", key));
+    }
+
+    let mut chars = value.trim_start().chars().peekable();
+    let value = match chars.peek() {
+        Some('\'') => {
+            chars.next();
+            let literal: String = chars.by_ref().take_while(|c| *c != '\'').collect();
+            check_rest(chars)?;
+            literal
+        }
+        Some('"') => {
+            chars.next();
+            let value = scan(&mut chars, Some('"'), &lookup)?;
+            check_rest(chars)?;
+            value
+        }
+        _ => scan(&mut chars, None, &lookup)?.trim_end().to_string(),
+    };
+    Ok(Some((key.to_string(), value)))
+}
+
+/// only a comment may follow a quoted value
+fn check_rest(rest: Peekable<Chars>) -> Result<(), String> {
+    let rest: String = rest.collect();
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected {:?} after quoted value", rest))
+    }
+}
+
+/// read a value up to the closing `quote` (or a ` #` comment when unquoted), expanding references
+fn scan<F>(chars: &mut Peekable<Chars>, quote: Option<char>, lookup: &F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut value = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match (chars.next(), quote) {
+                (Some('$'), _) => value.push('$'),
+                (Some('n'), Some(_)) => value.push('\n'),
+                (Some('r'), Some(_)) => value.push('\r'),
+                (Some('t'), Some(_)) => value.push('\t'),
+                (Some(c @ ('"' | '\\')), Some(_)) => value.push(c),
+                (Some(c), _) => {
+                    value.push('\\');
+                    value.push(c);
+                }
+                (None, _) => value.push('\\'),
+            },
+            '$' => value.push_str(&expand(chars, lookup)?),
+            '#' if quote.is_none() && value.ends_with(char::is_whitespace) => return Ok(value),
+            c if Some(c) == quote => return Ok(value),
+            c => value.push(c),
+        }
+    }
+    match quote {
+        Some(q) => Err(format!("missing closing {}", q)),
+        None => Ok(value),
+    }
+}
+
+/// expand the reference following a `$`
+fn expand<F>(chars: &mut Peekable<Chars>, lookup: &F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let is_name = |c: &char| c.is_ascii_alphanumeric() || *c == '_';
+    if chars.peek() != Some(&'{') {
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(is_name) {
+            name.push(c);
+        }
+        if name.is_empty() {
+            return Ok("$".to_string());
+        }
+        return Ok(lookup(&name).unwrap_or_default());
+    }
+
+    chars.next();
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(is_name) {
+        name.push(c);
+    }
+    let (default, when_empty) = match chars.next() {
+        Some('}') => (None, false),
+        Some(':') if chars.next_if_eq(&'-').is_some() => (Some(scan_default(chars, lookup)?), true),
+        Some('-') => (Some(scan_default(chars, lookup)?), false),
+        _ => return Err(format!("invalid reference to {:?}", name)),
+    };
+    if name.is_empty() {
+        return Err("empty variable reference".to_string());
+    }
+    Ok(match (lookup(&name), default) {
+        (Some(value), Some(default)) if when_empty && value.is_empty() => default,
+        (Some(value), _) => value,
+        (None, default) => default.unwrap_or_default(),
+    })
+}
+
+/// the default of `${VAR:-default}` up to the closing `}`, may hold references itself
+fn scan_default<F>(chars: &mut Peekable<Chars>, lookup: &F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut value = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '}' => return Ok(value),
+            '$' => value.push_str(&expand(chars, lookup)?),
+            c => value.push(c),
+        }
+    }
+    Err("missing closing }".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use crate::config::envfile::{encrypt_keys, parse_line, EnvAction, EnvFiles};
+    use crate::config::{overrides, EnvOverrides};
+    use crate::crypto::{decrypt_by_key, encrypt_by_key};
+
+    #[test]
+    fn parse_values() {
+        let lookup = |name: &str| match name {
+            "HOST" => Some("db".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let parse = |line: &str| parse_line(line, lookup).unwrap().map(|(_, v)| v);
+        assert_eq!(parse("# comment"), None);
+        assert_eq!(parse("A=plain value # comment"), Some("plain value".into()));
+        assert_eq!(parse("export A=${HOST}:5432"), Some("db:5432".into()));
+        assert_eq!(parse("A=$HOST/$NOPE/x"), Some("db//x".into()));
+        assert_eq!(parse("A=${NOPE:-${HOST}}"), Some("db".into()));
+        assert_eq!(parse("A=${EMPTY:-fallback}"), Some("fallback".into()));
+        assert_eq!(parse("A=${EMPTY-fallback}"), Some("".into()));
+        assert_eq!(parse("A='${HOST} #x'"), Some("${HOST} #x".into()));
+        assert_eq!(
+            parse(r#"A="a\n\"${HOST}\" \$HOST""#),
+            Some("a\n\"db\" $HOST".into())
+        );
+        assert!(parse_line("A=\"open", lookup).is_err());
+        assert!(parse_line("NOT A PAIR", lookup).is_err());
+    }
+
+    #[test]
+    fn load_files() {
+        let dir = env::temp_dir().join("busylib_envfile_test");
+        fs::create_dir_all(&dir).unwrap();
+        let base = dir.join(".env");
+        let local = dir.join(".env.local");
+        fs::write(
+            &base,
+            "ENVFILE_TEST_HOST=localhost\nENVFILE_TEST_URL=http://${ENVFILE_TEST_HOST}:${ENVFILE_TEST_PORT:-80}\nENVFILE_TEST_USER=app\n",
+        )
+        .unwrap();
+        fs::write(
+            &local,
+            "ENVFILE_TEST_HOST=10.0.0.1\nENVFILE_TEST_URL=${ENVFILE_TEST_URL}/api\n",
+        )
+        .unwrap();
+        let _guard = EnvOverrides::new().set("ENVFILE_TEST_USER", "root").enter();
+
+        let report = EnvFiles::new()
+            .file(&base)
+            .file(&local)
+            .file(dir.join("missing"))
+            .load()
+            .unwrap();
+        assert_eq!(report.files, vec![base, local]);
+        assert_eq!(env::var("ENVFILE_TEST_HOST").unwrap(), "10.0.0.1");
+        assert_eq!(
+            env::var("ENVFILE_TEST_URL").unwrap(),
+            "http://localhost:80/api"
+        );
+        assert_eq!(overrides::var("ENVFILE_TEST_USER").unwrap(), "root");
+        assert!(env::var("ENVFILE_TEST_USER").is_err());
+        assert_eq!(report.keys(EnvAction::Skipped), vec!["ENVFILE_TEST_USER"]);
+        assert_eq!(
+            report.keys(EnvAction::Overridden),
+            vec!["ENVFILE_TEST_HOST", "ENVFILE_TEST_URL"]
+        );
+    }
+
+    #[test]
+    fn encrypt_selected_keys() {
+        let content = "# db\nHOST=db\nexport DB_PASSWORD=\"s3cret\" # rotate yearly\nDB_URL=pg://${HOST}\nTOKEN=ENC(abc)\n";
+        let rewritten = encrypt_keys(content, &["DB_PASSWORD", "DB_URL", "TOKEN"], |v| {
+            encrypt_by_key(v.to_string(), "master")
+        })
+        .unwrap();
+        let lines: Vec<&str> = rewritten.lines().collect();
+        assert_eq!(lines[..2], ["# db", "HOST=db"]);
+        assert_eq!(lines[4], "TOKEN=ENC(abc)");
+        assert!(rewritten.ends_with('\n'));
+        let decrypt = |line: &str, name: &str| {
+            let value = line.strip_prefix(name).unwrap();
+            decrypt_by_key(value[4..value.len() - 1].to_string(), "master")
+        };
+        assert_eq!(decrypt(lines[2], "export DB_PASSWORD="), "s3cret");
+        assert_eq!(decrypt(lines[3], "DB_URL="), "pg://db");
+
+        let err = encrypt_keys(content, &["DB_PASWORD"], |v| v.to_string()).unwrap_err();
+        assert!(err.to_string().contains("DB_PASWORD"));
+    }
+}
@@ -0,0 +1,666 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::config::dump;
+use crate::config::envfile::{find_dotenv, parse_file};
+use crate::config::overrides;
+use crate::config::schema::{self, KeyType};
+use crate::config::secret::{decrypt_value, is_encrypted};
+use crate::config::Source;
+use crate::errors::ConfigError;
+
+/// Layered configuration loader.
+///
+/// Layers are merged in this order, later ones overriding earlier ones:
+/// built-in defaults, `.env`, an optional TOML/JSON file, process env vars.
+///
+/// Variables map to keys by splitting their name on [`ENV_SEPARATOR`]: `DB__POOL_SIZE` sets
+/// `db.pool_size`, `PEERS__0` the first item of `peers` and `LIMITS__API` the `api` entry of
+/// the `limits` map. Without a prefix only variables whose first segment matches a top-level
+/// key are read. With [`ConfigLoader::env_prefix`] every `<PREFIX>__...` variable is read and
+/// all others are ignored, so several binaries can share one environment.
+///
+/// ```rust,ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct AppConfig {
+///     host: String,
+///     port: u16,
+/// }
+///
+/// let loaded: Loaded<AppConfig> = ConfigLoader::new()
+///     .defaults(&AppConfig { host: "127.0.0.1".into(), port: 8080 })
+///     .file("config/app.toml")
+///     .load()?;
+/// println!("port {} comes from {}", loaded.value.port, loaded.source("port").unwp());
+/// ```
+pub struct ConfigLoader {
+    defaults: Map<String, Value>,
+    defaults_error: Option<String>,
+    dotenv: bool,
+    dotenv_file: Option<PathBuf>,
+    file: Option<PathBuf>,
+    env: bool,
+    env_prefix: Option<String>,
+}
+
+/// Separates the segments of a nested variable name, e.g. `MYSVC__DB__HOST`
+pub const ENV_SEPARATOR: &str = "__";
+
+/// A loaded configuration together with the source of every leaf value,
+/// keyed by dotted path (e.g. `db.host`)
+#[derive(Debug)]
+pub struct Loaded<T> {
+    pub value: T,
+    pub sources: BTreeMap<String, Source>,
+}
+
+impl<T> Loaded<T> {
+    pub fn source(&self, key: &str) -> Option<&Source> {
+        self.sources.get(key)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl Default for ConfigLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConfigLoader {
+    pub fn new() -> Self {
+        Self {
+            defaults: Map::new(),
+            defaults_error: None,
+            dotenv: true,
+            dotenv_file: None,
+            file: None,
+            env: true,
+            env_prefix: None,
+        }
+    }
+
+    /// `defaults` must serialize to a map, typically it is the config struct itself.
+    /// Otherwise [`ConfigLoader::load`] fails.
+    pub fn defaults<D: Serialize>(mut self, defaults: &D) -> Self {
+        match serde_json::to_value(defaults) {
+            Ok(Value::Object(map)) => {
+                self.defaults = map;
+                self.defaults_error = None;
+            }
+            Ok(_) => self.defaults_error = Some("config defaults must serialize to a map".into()),
+            Err(e) => {
+                self.defaults_error = Some(format!("failed to serialize config defaults: {}", e))
+            }
+        }
+        self
+    }
+
+    /// Whether to read the `.env` layer, enabled by default. The layer is the file
+    /// [`crate::config::dotenv`] loads, parsed the same way, but it is not exported to the
+    /// process env.
+    pub fn dotenv(mut self, enabled: bool) -> Self {
+        self.dotenv = enabled;
+        self
+    }
+
+    /// Read the `.env` layer from `path` instead of searching for a `.env` file
+    pub fn dotenv_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dotenv_file = Some(path.into());
+        self
+    }
+
+    /// TOML or JSON file, picked by extension. A missing file is skipped.
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// Whether to read the process env layer, enabled by default
+    pub fn env(mut self, enabled: bool) -> Self {
+        self.env = enabled;
+        self
+    }
+
+    /// Only read `<PREFIX>__...` variables from `.env` and the process env
+    pub fn env_prefix(mut self, prefix: &str) -> Self {
+        self.env_prefix = Some(prefix.to_uppercase());
+        self
+    }
+
+    pub fn load<T: DeserializeOwned>(&self) -> Result<Loaded<T>, ConfigError> {
+        if let Some(details) = &self.defaults_error {
+            return Err(ConfigError {
+                details: details.clone(),
+            });
+        }
+        let mut merged = Map::new();
+        let mut sources = BTreeMap::new();
+        let mut decrypted = HashSet::new();
+        merge(
+            &mut merged,
+            &self.defaults,
+            &Source::Default,
+            "",
+            &mut sources,
+        );
+        if self.env {
+            self.register_defaults(&self.defaults, &[]);
+        }
+
+        let file_layer = match &self.file {
+            Some(path) if path.exists() => Some((read_file(path)?, path.clone())),
+            _ => None,
+        };
+        let mut known_keys: Vec<String> = merged.keys().cloned().collect();
+        if let Some((layer, _)) = &file_layer {
+            known_keys.extend(layer.keys().filter(|k| !merged.contains_key(*k)).cloned());
+        }
+
+        let mut dotenv_vars = HashMap::new();
+        if self.dotenv {
+            let path = match &self.dotenv_file {
+                Some(path) => Some(path.clone()).filter(|p| p.exists()),
+                None => find_dotenv().map_err(|e| ConfigError {
+                    details: format!("failed to locate .env: {}", e),
+                })?,
+            };
+            if let Some(path) = path {
+                dotenv_vars = parse_file(&path)?;
+                let layer = self.env_layer(&known_keys, &dotenv_vars, &merged, &mut decrypted)?;
+                merge(&mut merged, &layer, &Source::DotEnv(path), "", &mut sources);
+            }
+        }
+
+        if let Some((mut layer, path)) = file_layer {
+            for (key, value) in layer.iter_mut() {
+                decrypt_strings(key, value, &mut decrypted)?;
+            }
+            merge(&mut merged, &layer, &Source::File(path), "", &mut sources);
+        }
+
+        if self.env {
+            // values of a `.env` exported earlier, e.g. by `dotenv()`, stay in the `.env` layer
+            let process_vars: HashMap<String, String> = overrides::vars()
+                .into_iter()
+                .filter(|(k, v)| dotenv_vars.get(k) != Some(v))
+                .collect();
+            let layer = self.env_layer(&known_keys, &process_vars, &merged, &mut decrypted)?;
+            merge(&mut merged, &layer, &Source::Env, "", &mut sources);
+        }
+
+        let merged = Value::Object(merged);
+        for (path, source) in &sources {
+            let pointer = format!(
+                "/{}",
+                path.replace('~', "~0").replace('/', "~1").replace('.', "/")
+            );
+            let value = match merged.pointer(&pointer) {
+                Some(Value::String(s)) => s.clone(),
+                Some(other) => other.to_string(),
+                None => continue,
+            };
+            let nested = format!("{}.", path);
+            let was_decrypted = decrypted
+                .iter()
+                .any(|d| d == path || d.starts_with(&nested));
+            dump::record(path, Some(&value), source.clone(), was_decrypted);
+        }
+
+        let value = serde_json::from_value(merged).map_err(|e| ConfigError {
+            details: format!("failed to deserialize configuration: {}", e),
+        })?;
+        Ok(Loaded { value, sources })
+    }
+
+    /// register the variable of every default leaf for [`crate::config::schema`]
+    fn register_defaults(&self, defaults: &Map<String, Value>, path: &[String]) {
+        for (key, value) in defaults {
+            let mut path = path.to_vec();
+            path.push(key.to_uppercase());
+            match value {
+                Value::Object(map) if !map.is_empty() => self.register_defaults(map, &path),
+                _ => {
+                    let name = self
+                        .env_prefix
+                        .iter()
+                        .chain(path.iter())
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(ENV_SEPARATOR);
+                    let default = Some(value.clone()).filter(|v| !v.is_null());
+                    schema::register(&name, KeyType::of_value(value), default, false);
+                }
+            }
+        }
+    }
+
+    /// convert the variables addressing config keys to a json layer
+    fn env_layer(
+        &self,
+        known_keys: &[String],
+        vars: &HashMap<String, String>,
+        merged: &Map<String, Value>,
+        decrypted: &mut HashSet<String>,
+    ) -> Result<Map<String, Value>, ConfigError> {
+        let merged = Value::Object(merged.clone());
+        let mut layer = Value::Object(Map::new());
+        let mut names: Vec<&String> = vars.keys().collect();
+        names.sort();
+        for name in names {
+            let Some(segments) = self.env_segments(name, known_keys) else {
+                continue;
+            };
+            let mut existing = Some(&merged);
+            let mut path = Vec::new();
+            for segment in &segments {
+                let key = resolve_key(existing, segment);
+                existing = existing.and_then(|e| child(e, &key));
+                path.push(key);
+            }
+            let raw = &vars[name];
+            if is_encrypted(raw) {
+                decrypted.insert(path.join("."));
+            }
+            let raw = decrypt_value(name, raw.clone()).map_err(|e| ConfigError {
+                details: e.to_string(),
+            })?;
+            insert_path(&mut layer, &path, coerce(&raw, existing), Some(&merged));
+        }
+        match layer {
+            Value::Object(map) => Ok(map),
+            _ => Ok(Map::new()),
+        }
+    }
+
+    /// split `name` into lower-cased key segments, `None` if it does not address this config
+    fn env_segments(&self, name: &str, known_keys: &[String]) -> Option<Vec<String>> {
+        let rest = match &self.env_prefix {
+            Some(prefix) => name
+                .strip_prefix(prefix.as_str())?
+                .strip_prefix(ENV_SEPARATOR)?,
+            None => name,
+        };
+        let segments: Vec<String> = rest
+            .split(ENV_SEPARATOR)
+            .map(|s| s.to_lowercase())
+            .collect();
+        if segments.iter().any(|s| s.is_empty()) {
+            return None;
+        }
+        if self.env_prefix.is_none()
+            && !known_keys
+                .iter()
+                .any(|k| k.eq_ignore_ascii_case(&segments[0]))
+        {
+            return None;
+        }
+        Some(segments)
+    }
+}
+
+/// the key of `existing` matching `segment` case-insensitively, or `segment` itself
+fn resolve_key(existing: Option<&Value>, segment: &str) -> String {
+    match existing {
+        Some(Value::Object(map)) => map
+            .keys()
+            .find(|k| k.eq_ignore_ascii_case(segment))
+            .cloned()
+            .unwrap_or_else(|| segment.to_string()),
+        _ => segment.to_string(),
+    }
+}
+
+fn child<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+        _ => None,
+    }
+}
+
+/// Set `value` at `path` below `container`, creating maps and arrays on the way.
+/// `existing` is the merged value at the same place: it decides between arrays and maps,
+/// and array items that are not overridden are kept from it.
+fn insert_path(container: &mut Value, path: &[String], value: Value, existing: Option<&Value>) {
+    let Some((head, rest)) = path.split_first() else {
+        return;
+    };
+    let existing = existing.and_then(|e| child(e, head));
+    let slot = match container {
+        Value::Object(map) => map.entry(head.clone()).or_insert(Value::Null),
+        Value::Array(items) => {
+            let Ok(index) = head.parse::<usize>() else {
+                return;
+            };
+            if items.len() <= index {
+                items.resize(index + 1, Value::Null);
+            }
+            &mut items[index]
+        }
+        _ => return,
+    };
+    if rest.is_empty() {
+        *slot = value;
+        return;
+    }
+    if !slot.is_object() && !slot.is_array() {
+        *slot = match existing {
+            Some(Value::Array(items)) => Value::Array(items.clone()),
+            Some(Value::Object(_)) => Value::Object(Map::new()),
+            _ if rest[0].parse::<usize>().is_ok() => Value::Array(Vec::new()),
+            _ => Value::Object(Map::new()),
+        };
+    }
+    insert_path(slot, rest, value, existing);
+}
+
+pub(crate) fn read_file(path: &Path) -> Result<Map<String, Value>, ConfigError> {
+    let content = fs::read_to_string(path).map_err(|e| ConfigError {
+        details: format!("failed to read {}: {}", path.display(), e),
+    })?;
+    let parsed: Result<Value, String> = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&content).map_err(|e| e.to_string()),
+        Some("json") => serde_json::from_str(&content).map_err(|e| e.to_string()),
+        _ => Err("only .toml and .json files are supported".to_string()),
+    };
+    match parsed {
+        Ok(Value::Object(map)) => Ok(map),
+        Ok(_) => Err(ConfigError {
+            details: format!("{} must contain a table at top level", path.display()),
+        }),
+        Err(e) => Err(ConfigError {
+            details: format!("failed to parse {}: {}", path.display(), e),
+        }),
+    }
+}
+
+/// decrypt every `ENC(...)` string in a file value, `path` names it in errors
+fn decrypt_strings(
+    path: &str,
+    value: &mut Value,
+    decrypted: &mut HashSet<String>,
+) -> Result<(), ConfigError> {
+    match value {
+        Value::String(s) => {
+            if is_encrypted(s) {
+                decrypted.insert(path.to_string());
+            }
+            *s = decrypt_value(path, std::mem::take(s)).map_err(|e| ConfigError {
+                details: e.to_string(),
+            })?;
+        }
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                decrypt_strings(&format!("{}.{}", path, key), value, decrypted)?;
+            }
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                decrypt_strings(&format!("{}.{}", path, i), value, decrypted)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Convert a raw string to the json type of the value it replaces.
+/// Without such a hint numbers, booleans, arrays and maps are inferred.
+pub(crate) fn coerce(raw: &str, hint: Option<&Value>) -> Value {
+    match hint {
+        Some(Value::String(_)) => Value::String(raw.to_string()),
+        Some(Value::Bool(_)) => match raw.to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Value::Bool(true),
+            "false" | "0" | "no" | "off" => Value::Bool(false),
+            _ => Value::String(raw.to_string()),
+        },
+        Some(Value::Number(_)) | Some(Value::Array(_)) | Some(Value::Object(_)) => {
+            serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+        }
+        Some(Value::Null) | None => match serde_json::from_str(raw) {
+            Ok(Value::String(_)) | Ok(Value::Null) | Err(_) => Value::String(raw.to_string()),
+            Ok(value) => value,
+        },
+    }
+}
+
+/// deep merge `src` into `dst`, recording the source of every replaced leaf
+fn merge(
+    dst: &mut Map<String, Value>,
+    src: &Map<String, Value>,
+    source: &Source,
+    prefix: &str,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    for (key, value) in src {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match (dst.get_mut(key), value) {
+            (Some(Value::Object(dst_map)), Value::Object(src_map)) => {
+                merge(dst_map, src_map, source, &path, sources);
+            }
+            _ => {
+                let nested = format!("{}.", path);
+                sources.retain(|k, _| k != &path && !k.starts_with(&nested));
+                record_sources(value, &path, source, sources);
+                dst.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn record_sources(
+    value: &Value,
+    path: &str,
+    source: &Source,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                record_sources(value, &format!("{}.{}", path, key), source, sources);
+            }
+        }
+        _ => {
+            sources.insert(path.to_string(), source.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::config::{ConfigLoader, EnvOverrides, Source};
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Db {
+        host: String,
+        pool_size: u32,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct AppConfig {
+        loader_test_name: String,
+        loader_test_port: u16,
+        loader_test_debug: bool,
+        db: Db,
+    }
+
+    #[test]
+    fn load_layers() {
+        let dir = env::temp_dir().join("busylib_loader_test");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("app.toml");
+        fs::write(
+            &file,
+            "loader_test_port = 9090\n[db]\nhost = \"db.internal\"\n",
+        )
+        .unwrap();
+        let _guard = EnvOverrides::new().set("LOADER_TEST_DEBUG", "yes").enter();
+
+        let defaults = AppConfig {
+            loader_test_name: "app".into(),
+            loader_test_port: 8080,
+            loader_test_debug: false,
+            db: Db {
+                host: "localhost".into(),
+                pool_size: 4,
+            },
+        };
+        let loaded = ConfigLoader::new()
+            .defaults(&defaults)
+            .dotenv(false)
+            .file(&file)
+            .load::<AppConfig>()
+            .unwrap();
+
+        assert_eq!(loaded.value.loader_test_name, "app");
+        assert_eq!(loaded.value.loader_test_port, 9090);
+        assert!(loaded.value.loader_test_debug);
+        assert_eq!(loaded.value.db.host, "db.internal");
+        assert_eq!(loaded.value.db.pool_size, 4);
+        assert_eq!(loaded.source("loader_test_name"), Some(&Source::Default));
+        assert_eq!(loaded.source("loader_test_port"), Some(&Source::File(file)));
+        assert_eq!(loaded.source("loader_test_debug"), Some(&Source::Env));
+        assert_eq!(loaded.source("db.pool_size"), Some(&Source::Default));
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Peer {
+        host: String,
+        port: u16,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct SvcConfig {
+        name: String,
+        db: Db,
+        peers: Vec<Peer>,
+        tags: Vec<String>,
+        limits: BTreeMap<String, u32>,
+    }
+
+    #[test]
+    fn load_nested_env_with_prefix() {
+        let _guard = EnvOverrides::new()
+            .set("NESTSVC__DB__POOL_SIZE", "16")
+            .set("NESTSVC__PEERS__1__HOST", "10.0.0.2")
+            .set("NESTSVC__PEERS__1__PORT", "7001")
+            .set("NESTSVC__TAGS__0", "blue")
+            .set("NESTSVC__LIMITS__API", "100")
+            .set("OTHERSVC__NAME", "other")
+            .enter();
+
+        let defaults = SvcConfig {
+            name: "nestsvc".into(),
+            db: Db {
+                host: "localhost".into(),
+                pool_size: 4,
+            },
+            peers: vec![Peer {
+                host: "10.0.0.1".into(),
+                port: 7000,
+            }],
+            tags: vec![],
+            limits: BTreeMap::new(),
+        };
+        let loaded = ConfigLoader::new()
+            .defaults(&defaults)
+            .dotenv(false)
+            .env_prefix("nestsvc")
+            .load::<SvcConfig>()
+            .unwrap();
+        let config = &loaded.value;
+
+        assert_eq!(config.name, "nestsvc");
+        assert_eq!(config.db.host, "localhost");
+        assert_eq!(config.db.pool_size, 16);
+        assert_eq!(config.peers.len(), 2);
+        assert_eq!(config.peers[0].host, "10.0.0.1");
+        assert_eq!(config.peers[1].host, "10.0.0.2");
+        assert_eq!(config.peers[1].port, 7001);
+        assert_eq!(config.tags, vec!["blue"]);
+        assert_eq!(config.limits.get("api"), Some(&100));
+        assert_eq!(loaded.source("db.pool_size"), Some(&Source::Env));
+        assert_eq!(loaded.source("db.host"), Some(&Source::Default));
+    }
+
+    #[test]
+    fn load_invalid_value() {
+        let _guard = EnvOverrides::new()
+            .set("LOADER_INVALID_PORT", "not a port")
+            .enter();
+        #[derive(Serialize, Deserialize)]
+        struct Config {
+            loader_invalid_port: u16,
+        }
+        let result = ConfigLoader::new()
+            .defaults(&Config {
+                loader_invalid_port: 1,
+            })
+            .dotenv(false)
+            .load::<Config>();
+        assert!(result.is_err());
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct DotenvConfig {
+        dotenv_test_level: String,
+        dotenv_test_name: String,
+    }
+
+    #[test]
+    fn load_dotenv_without_exporting() {
+        let dir = env::temp_dir().join("busylib_loader_test");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("loader.env");
+        fs::write(
+            &file,
+            "DOTENV_TEST_LEVEL=${DOTENV_TEST_UNSET:-a} # comment\nDOTENV_TEST_NAME='b c'\n",
+        )
+        .unwrap();
+
+        let loaded = ConfigLoader::new()
+            .defaults(&DotenvConfig {
+                dotenv_test_level: "x".into(),
+                dotenv_test_name: "y".into(),
+            })
+            .dotenv_file(&file)
+            .load::<DotenvConfig>()
+            .unwrap();
+        assert_eq!(loaded.value.dotenv_test_level, "a");
+        assert_eq!(loaded.value.dotenv_test_name, "b c");
+        assert_eq!(
+            loaded.source("dotenv_test_level"),
+            Some(&Source::DotEnv(file))
+        );
+        assert!(env::var("DOTENV_TEST_LEVEL").is_err());
+    }
+
+    #[test]
+    fn load_invalid_defaults() {
+        let err = ConfigLoader::new()
+            .defaults(&42)
+            .dotenv(false)
+            .load::<BTreeMap<String, String>>()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "config defaults must serialize to a map");
+    }
+}
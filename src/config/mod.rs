@@ -0,0 +1,459 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use arc_swap::ArcSwap;
+use log::error;
+use once_cell::sync::Lazy;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+
+use crate::config::schema::KeyType;
+use crate::errors::{DecryptError, EnvDecryptError, EnvError, EnvIssue, EnvIssueKind};
+
+pub mod dump;
+pub mod envfile;
+pub mod loader;
+pub mod overrides;
+pub mod profile;
+#[cfg(feature = "http-client")]
+pub mod remote;
+pub mod schema;
+pub mod secret;
+pub mod setting;
+pub mod watch;
+
+pub use envfile::{EnvAction, EnvFiles, EnvReport};
+pub use loader::{ConfigLoader, Loaded};
+pub use overrides::{EnvOverrideGuard, EnvOverrides};
+pub use profile::{active_profile, load_profile_dotenv, set_active_profile, Profile};
+#[cfg(feature = "http-client")]
+pub use remote::RemoteConfig;
+pub use setting::{GlobalSetting, Setting};
+pub use watch::{ConfigWatcher, Reloadable};
+
+/// A string setting read without locks. It cannot be subscribed to, declare a
+/// [`GlobalSetting`] with [`setting_with_default`] instead to be notified when a
+/// [`ConfigWatcher`] replaces the value.
+pub type GlobalString = Lazy<ArcSwap<String>>;
+pub type GlobalStaticStr = Lazy<ArcSwap<&'static str>>;
+
+/// Where a configuration value came from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// built-in default supplied by the caller
+    Default,
+    /// a `.env` file, read by [`ConfigLoader`] or [`ConfigWatcher`]
+    DotEnv(PathBuf),
+    /// a TOML/JSON configuration file
+    File(PathBuf),
+    /// a process environment variable
+    Env,
+    /// the file named by a `<NAME>_FILE` variable
+    SecretFile(PathBuf),
+    /// an in-process override, see [`EnvOverrides`]
+    Override,
+    /// a remote configuration endpoint, or its cached snapshot
+    Remote(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::DotEnv(path) => write!(f, "dotenv({})", path.display()),
+            Source::File(path) => write!(f, "file({})", path.display()),
+            Source::Env => write!(f, "env"),
+            Source::SecretFile(path) => write!(f, "secret_file({})", path.display()),
+            Source::Override => write!(f, "override"),
+            Source::Remote(url) => write!(f, "remote({})", url),
+        }
+    }
+}
+
+impl Serialize for Source {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Whether the active profile is [`Profile::Dev`], see [`active_profile`]
+pub fn debug_mode() -> bool {
+    *active_profile() == Profile::Dev
+}
+
+/// `ENC(...)` values are decrypted, see [`env_string_with_default`]. Readers only see a
+/// reloaded value the next time they load it, see [`setting_with_default`] for notifications.
+pub fn env_var_with_default(name: &str, default: &str) -> ArcSwap<String> {
+    ArcSwap::from_pointee(env_string_with_default(name, default))
+}
+
+/// Like [`env_var_with_default`], but the returned [`Setting`] notifies subscribers of changes
+pub fn setting_with_default(name: &str, default: &str) -> Setting {
+    Setting::new(env_string_with_default(name, default))
+}
+
+/// `ENC(...)` values are decrypted with [`secret::master_key`].
+/// If decryption fails the error is logged and `default` is returned.
+pub fn env_string_with_default(name: &str, default: &str) -> String {
+    match env_string_with_error(name, default) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("{}", e);
+            default.into()
+        }
+    }
+}
+
+/// Like [`env_string_with_default`], but return an error naming the variable if its
+/// `ENC(...)` value cannot be decrypted
+pub fn env_string_with_error(name: &str, default: &str) -> Result<String, EnvDecryptError> {
+    schema::register(name, KeyType::String, Some(default.into()), false);
+    match lookup(name)? {
+        Some(value) => Ok(value),
+        None => {
+            dump::record(name, Some(default), Source::Default, false);
+            Ok(default.into())
+        }
+    }
+}
+
+/// The single place variables are read from, decrypting `ENC(...)` values and recording
+/// the read for [`dump::effective_config`].
+///
+/// If `name` is unset but `<name>_FILE` is set, the value is read from that file without its
+/// trailing newline, following the Docker/Kubernetes secret convention. [`EnvOverrides`] of
+/// the current thread or task take precedence over the process env.
+pub(crate) fn lookup(name: &str) -> Result<Option<String>, EnvDecryptError> {
+    lookup_secret(name).map(|value| value.map(|(value, _)| value))
+}
+
+/// Like [`lookup`], also telling whether the value is secret: decrypted or read from a file
+fn lookup_secret(name: &str) -> Result<Option<(String, bool)>, EnvDecryptError> {
+    if let Some(raw) = overrides::var(name) {
+        let source = if overrides::is_overridden(name) {
+            Source::Override
+        } else {
+            Source::Env
+        };
+        return resolve(name, name, raw, source).map(Some);
+    }
+    let file_var = format!("{}_FILE", name);
+    let Some(path) = overrides::var(&file_var) else {
+        return Ok(None);
+    };
+    let raw = fs::read_to_string(&path).map_err(|e| EnvDecryptError {
+        name: file_var.clone(),
+        source: DecryptError {
+            details: format!("failed to read secret file {}: {}", path, e),
+        },
+    })?;
+    let raw = raw.trim_end_matches(['\r', '\n']).to_string();
+    let (value, _) = resolve(name, &file_var, raw, Source::SecretFile(path.into()))?;
+    Ok(Some((value, true)))
+}
+
+/// decrypt `raw` read from `var` and record it as the value of `name`, telling whether it was
+/// encrypted
+fn resolve(
+    name: &str,
+    var: &str,
+    raw: String,
+    source: Source,
+) -> Result<(String, bool), EnvDecryptError> {
+    let decrypted = secret::is_encrypted(&raw);
+    let value = secret::decrypt_value(var, raw)?;
+    dump::record(name, Some(&value), source, decrypted);
+    Ok((value, decrypted))
+}
+
+/// Parse `name` into `T`, return `default` if it is unset and an error if it cannot be parsed
+pub fn env_parse<T>(name: &str, default: T) -> Result<T, EnvError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    register::<T>(name, false, None);
+    match parse_var(name) {
+        Ok(Some(value)) => Ok(value),
+        Ok(None) => {
+            dump::record(name, None, Source::Default, false);
+            Ok(default)
+        }
+        Err(issue) => Err(EnvError {
+            issues: vec![issue],
+        }),
+    }
+}
+
+/// Like [`env_parse`], also recording `default` for [`schema::json_schema`],
+/// [`schema::markdown`] and [`schema::env_example`]
+pub fn env_parse_documented<T>(name: &str, default: T) -> Result<T, EnvError>
+where
+    T: FromStr + fmt::Display,
+    T::Err: fmt::Display,
+{
+    register::<T>(name, false, Some(default.to_string()));
+    env_parse(name, default)
+}
+
+/// Parse `name` into `T`, return an error if it is unset or cannot be parsed
+pub fn env_required<T>(name: &str) -> Result<T, EnvError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    register::<T>(name, true, None);
+    match parse_var(name) {
+        Ok(Some(value)) => Ok(value),
+        Ok(None) => Err(EnvError {
+            issues: vec![missing::<T>(name)],
+        }),
+        Err(issue) => Err(EnvError {
+            issues: vec![issue],
+        }),
+    }
+}
+
+/// Validate a batch of variables at once and report every problem in one [`EnvError`].
+///
+/// ```rust,ignore
+/// let mut env = EnvCollector::new();
+/// let host: String = env.required("DB_HOST");
+/// let port: u16 = env.parse("DB_PORT", 5432);
+/// env.finish()?;
+/// ```
+#[derive(Debug, Default)]
+pub struct EnvCollector {
+    issues: Vec<EnvIssue>,
+}
+
+impl EnvCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Like [`env_parse`], returns `default` and records the problem on error
+    pub fn parse<T>(&mut self, name: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        register::<T>(name, false, None);
+        match parse_var(name) {
+            Ok(Some(value)) => value,
+            Ok(None) => {
+                dump::record(name, None, Source::Default, false);
+                default
+            }
+            Err(issue) => {
+                self.issues.push(issue);
+                default
+            }
+        }
+    }
+
+    /// Like [`EnvCollector::parse`], also recording `default` like [`env_parse_documented`]
+    pub fn parse_documented<T>(&mut self, name: &str, default: T) -> T
+    where
+        T: FromStr + fmt::Display,
+        T::Err: fmt::Display,
+    {
+        register::<T>(name, false, Some(default.to_string()));
+        self.parse(name, default)
+    }
+
+    /// Like [`env_required`], returns `T::default()` and records the problem on error.
+    /// The placeholder must not be used unless [`EnvCollector::finish`] succeeds.
+    pub fn required<T>(&mut self, name: &str) -> T
+    where
+        T: FromStr + Default,
+        T::Err: fmt::Display,
+    {
+        register::<T>(name, true, None);
+        match parse_var(name) {
+            Ok(Some(value)) => value,
+            Ok(None) => {
+                self.issues.push(missing::<T>(name));
+                T::default()
+            }
+            Err(issue) => {
+                self.issues.push(issue);
+                T::default()
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), EnvError> {
+        if self.issues.is_empty() {
+            Ok(())
+        } else {
+            Err(EnvError {
+                issues: self.issues,
+            })
+        }
+    }
+}
+
+fn parse_var<T>(name: &str) -> Result<Option<T>, EnvIssue>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let (raw, secret) = match lookup_secret(name) {
+        Ok(Some(found)) => found,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            return Err(EnvIssue {
+                name: name.to_string(),
+                expected: type_name::<T>(),
+                kind: EnvIssueKind::Undecryptable(e.into_inner()),
+            })
+        }
+    };
+    raw.parse().map(Some).map_err(|e: T::Err| EnvIssue {
+        name: name.to_string(),
+        expected: type_name::<T>(),
+        kind: EnvIssueKind::Unparsable {
+            value: raw,
+            reason: e.to_string(),
+            secret,
+        },
+    })
+}
+
+/// register `name` for [`schema::json_schema`] with the type it is parsed into and the text
+/// of its default
+fn register<T>(name: &str, required: bool, default: Option<String>) {
+    let kind = KeyType::of_type(&type_name::<T>());
+    let default = default.map(|text| match kind {
+        KeyType::String => Value::String(text),
+        _ => serde_json::from_str(&text).unwrap_or(Value::String(text)),
+    });
+    schema::register(name, kind, default, required);
+}
+
+fn missing<T>(name: &str) -> EnvIssue {
+    EnvIssue {
+        name: name.to_string(),
+        expected: type_name::<T>(),
+        kind: EnvIssueKind::Missing,
+    }
+}
+
+/// `alloc::vec::Vec<alloc::string::String>` -> `Vec<String>`
+fn type_name<T>() -> String {
+    let mut short = String::new();
+    let mut token = String::new();
+    for c in std::any::type_name::<T>().chars() {
+        if c.is_alphanumeric() || c == '_' || c == ':' {
+            token.push(c);
+        } else {
+            short.push_str(token.rsplit("::").next().unwrap_or_default());
+            token.clear();
+            short.push(c);
+        }
+    }
+    short.push_str(token.rsplit("::").next().unwrap_or_default());
+    short
+}
+
+/// Load the first `.env` file in the current directory and its parents with [`EnvFiles`],
+/// keeping variables that are already set. [`ConfigLoader`] reads the same file the same way.
+pub fn dotenv() -> dotenv::Result<PathBuf> {
+    let path = envfile::find_dotenv()
+        .map_err(dotenv::Error::Io)?
+        .ok_or_else(|| {
+            dotenv::Error::Io(io::Error::new(io::ErrorKind::NotFound, "path not found"))
+        })?;
+    EnvFiles::new()
+        .file(&path)
+        .load()
+        .map_err(|e| dotenv::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use crate::config::{
+        env_parse, env_required, env_string_with_default, env_string_with_error, EnvCollector,
+        EnvOverrides,
+    };
+    use crate::errors::EnvIssueKind;
+
+    #[test]
+    fn typed_getters() {
+        let _guard = EnvOverrides::new()
+            .set("TYPED_TEST_PORT", "9090")
+            .set("TYPED_TEST_BAD_PORT", "90x")
+            .enter();
+        assert_eq!(env_parse("TYPED_TEST_PORT", 80u16).unwrap(), 9090);
+        assert_eq!(env_parse("TYPED_TEST_UNSET_PORT", 80u16).unwrap(), 80);
+        assert!(env_parse("TYPED_TEST_BAD_PORT", 80u16).is_err());
+        assert_eq!(env_required::<u16>("TYPED_TEST_PORT").unwrap(), 9090);
+        assert!(env_required::<u16>("TYPED_TEST_UNSET_PORT").is_err());
+    }
+
+    #[test]
+    fn read_secret_file() {
+        let dir = env::temp_dir().join("busylib_secret_file_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("db_password");
+        std::fs::write(&file, "s3cret\n").unwrap();
+        let file = file.to_str().unwrap();
+        let missing = dir.join("missing");
+        let _guard = EnvOverrides::new()
+            .set("SECRET_FILE_TEST_PASSWORD_FILE", file)
+            .set("SECRET_FILE_TEST_PORT", "5432")
+            .set("SECRET_FILE_TEST_PORT_FILE", file)
+            .set("SECRET_FILE_TEST_MISSING_FILE", missing.to_str().unwrap())
+            .set("SECRET_FILE_TEST_WORKERS_FILE", file)
+            .enter();
+
+        assert_eq!(
+            env_string_with_default("SECRET_FILE_TEST_PASSWORD", ""),
+            "s3cret"
+        );
+        assert_eq!(env_required::<u16>("SECRET_FILE_TEST_PORT").unwrap(), 5432);
+        let err = env_string_with_error("SECRET_FILE_TEST_MISSING", "").unwrap_err();
+        assert_eq!(err.name(), "SECRET_FILE_TEST_MISSING_FILE");
+
+        // an unparsable secret is not echoed in the error
+        let err = env_parse("SECRET_FILE_TEST_WORKERS", 4u16).unwrap_err();
+        assert!(!err.to_string().contains("s3cret"));
+        assert!(err
+            .to_string()
+            .contains(r#"SECRET_FILE_TEST_WORKERS="******""#));
+    }
+
+    #[test]
+    fn collect_all_issues() {
+        let _guard = EnvOverrides::new()
+            .set("COLLECT_TEST_WORKERS", "many")
+            .set("COLLECT_TEST_HOST", "localhost")
+            .enter();
+        let mut collector = EnvCollector::new();
+        let host: String = collector.required("COLLECT_TEST_HOST");
+        let workers: usize = collector.parse("COLLECT_TEST_WORKERS", 4);
+        let _bind: std::net::IpAddr = collector.parse("COLLECT_TEST_BIND", [0, 0, 0, 0].into());
+        let _token: String = collector.required("COLLECT_TEST_TOKEN");
+        assert_eq!(host, "localhost");
+        assert_eq!(workers, 4);
+
+        let err = collector.finish().unwrap_err();
+        assert_eq!(err.issues().len(), 2);
+        assert_eq!(err.issues()[0].name, "COLLECT_TEST_WORKERS");
+        assert_eq!(err.issues()[0].expected, "usize");
+        assert!(matches!(
+            err.issues()[0].kind,
+            EnvIssueKind::Unparsable { .. }
+        ));
+        assert_eq!(err.issues()[1].name, "COLLECT_TEST_TOKEN");
+        assert_eq!(err.issues()[1].expected, "String");
+        assert!(matches!(err.issues()[1].kind, EnvIssueKind::Missing));
+    }
+}
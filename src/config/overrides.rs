@@ -0,0 +1,228 @@
+//! In-process overrides of environment variables, so tests can run in parallel without
+//! mutating `std::env`.
+//!
+//! Every getter of the config module, the master key, the active profile and
+//! [`crate::config::ConfigLoader`] check the overrides before the process env. Overrides are
+//! scoped to the current thread by [`EnvOverrides::enter`] or to the current task by
+//! [`EnvOverrides::scope`]. Tasks spawned inside a scope do not inherit it.
+//!
+//! ```rust,ignore
+//! #[test]
+//! fn uses_test_database() {
+//!     let _guard = EnvOverrides::new()
+//!         .set("DB_HOST", "127.0.0.1")
+//!         .unset("DB_PASSWORD")
+//!         .enter();
+//!     assert_eq!(env_string_with_default("DB_HOST", ""), "127.0.0.1");
+//! }
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// `None` hides the variable
+type Layer = HashMap<String, Option<String>>;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static THREAD_LAYERS: RefCell<Vec<(u64, Arc<Layer>)>> = const { RefCell::new(Vec::new()) };
+}
+
+tokio::task_local! {
+    static TASK_LAYER: Arc<Layer>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EnvOverrides {
+    values: Layer,
+}
+
+/// Removes its overrides when dropped, must stay on the thread that created it
+#[must_use = "the overrides are removed when the guard is dropped"]
+pub struct EnvOverrideGuard {
+    id: u64,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl EnvOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(mut self, name: &str, value: &str) -> Self {
+        self.values
+            .insert(name.to_string(), Some(value.to_string()));
+        self
+    }
+
+    /// Hide `name` as if it was not set in the process env
+    pub fn unset(mut self, name: &str) -> Self {
+        self.values.insert(name.to_string(), None);
+        self
+    }
+
+    /// Apply the overrides on the current thread until the guard is dropped
+    pub fn enter(self) -> EnvOverrideGuard {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        THREAD_LAYERS.with(|layers| layers.borrow_mut().push((id, Arc::new(self.values))));
+        EnvOverrideGuard {
+            id,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Run `future` with the overrides applied to the current task, on top of the overrides
+    /// of an enclosing scope
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        let mut layer = TASK_LAYER
+            .try_with(|outer| outer.as_ref().clone())
+            .unwrap_or_default();
+        layer.extend(self.values);
+        TASK_LAYER.scope(Arc::new(layer), future).await
+    }
+}
+
+impl Drop for EnvOverrideGuard {
+    fn drop(&mut self) {
+        let _ =
+            THREAD_LAYERS.try_with(|layers| layers.borrow_mut().retain(|(id, _)| *id != self.id));
+    }
+}
+
+/// `Some` if `name` is overridden, holding `None` if it is hidden
+fn get(name: &str) -> Option<Option<String>> {
+    if let Ok(Some(value)) = TASK_LAYER.try_with(|layer| layer.get(name).cloned()) {
+        return Some(value);
+    }
+    THREAD_LAYERS
+        .try_with(|layers| {
+            layers
+                .borrow()
+                .iter()
+                .rev()
+                .find_map(|(_, layer)| layer.get(name).cloned())
+        })
+        .ok()
+        .flatten()
+}
+
+/// Whether `name` is overridden in the current thread or task
+pub fn is_overridden(name: &str) -> bool {
+    get(name).is_some()
+}
+
+/// [`std::env::var`] with overrides applied
+pub fn var(name: &str) -> Option<String> {
+    match get(name) {
+        Some(value) => value,
+        None => env::var(name).ok(),
+    }
+}
+
+/// [`std::env::vars`] with overrides applied
+pub fn vars() -> HashMap<String, String> {
+    let mut visible: Layer = HashMap::new();
+    let _ = THREAD_LAYERS.try_with(|layers| {
+        for (_, layer) in layers.borrow().iter() {
+            visible.extend(layer.as_ref().clone());
+        }
+    });
+    let _ = TASK_LAYER.try_with(|layer| visible.extend(layer.as_ref().clone()));
+
+    let mut vars: HashMap<String, String> = env::vars().collect();
+    for (name, value) in visible {
+        match value {
+            Some(value) => vars.insert(name, value),
+            None => vars.remove(&name),
+        };
+    }
+    vars
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use crate::config::dump::effective_config;
+    use crate::config::overrides::{is_overridden, var, EnvOverrides};
+    use crate::config::{debug_mode, env_parse, env_string_with_default, Source};
+
+    #[test]
+    fn thread_overrides() {
+        env::set_var("OVERRIDE_TEST_HIDDEN", "visible");
+        {
+            let _guard = EnvOverrides::new()
+                .set("OVERRIDE_TEST_PORT", "9090")
+                .unset("OVERRIDE_TEST_HIDDEN")
+                .set("BUSYLIB_PROFILE", "dev")
+                .enter();
+            assert_eq!(env_parse("OVERRIDE_TEST_PORT", 80u16).unwrap(), 9090);
+            assert_eq!(
+                env_string_with_default("OVERRIDE_TEST_HIDDEN", "default"),
+                "default"
+            );
+            assert!(debug_mode());
+            let entries = effective_config();
+            let port = entries.iter().find(|e| e.key == "OVERRIDE_TEST_PORT");
+            assert_eq!(port.unwrap().source, Source::Override);
+            {
+                let _inner = EnvOverrides::new()
+                    .set("OVERRIDE_TEST_PORT", "9091")
+                    .enter();
+                assert_eq!(var("OVERRIDE_TEST_PORT").as_deref(), Some("9091"));
+            }
+            assert_eq!(var("OVERRIDE_TEST_PORT").as_deref(), Some("9090"));
+        }
+        assert!(!is_overridden("OVERRIDE_TEST_PORT"));
+        assert_eq!(
+            env_string_with_default("OVERRIDE_TEST_HIDDEN", "default"),
+            "visible"
+        );
+    }
+
+    #[test]
+    fn parallel_thread_overrides() {
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    let value = i.to_string();
+                    let _guard = EnvOverrides::new()
+                        .set("OVERRIDE_TEST_SHARED", &value)
+                        .enter();
+                    std::thread::yield_now();
+                    assert_eq!(env_string_with_default("OVERRIDE_TEST_SHARED", ""), value);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn task_overrides() {
+        let outer = EnvOverrides::new()
+            .set("OVERRIDE_TEST_TASK_HOST", "outer")
+            .set("OVERRIDE_TEST_TASK_PORT", "1");
+        outer
+            .scope(async {
+                let inner = EnvOverrides::new().set("OVERRIDE_TEST_TASK_HOST", "inner");
+                inner
+                    .scope(async {
+                        tokio::task::yield_now().await;
+                        assert_eq!(var("OVERRIDE_TEST_TASK_HOST").as_deref(), Some("inner"));
+                        assert_eq!(var("OVERRIDE_TEST_TASK_PORT").as_deref(), Some("1"));
+                    })
+                    .await;
+                assert_eq!(var("OVERRIDE_TEST_TASK_HOST").as_deref(), Some("outer"));
+            })
+            .await;
+        assert_eq!(var("OVERRIDE_TEST_TASK_HOST"), None);
+    }
+}
@@ -0,0 +1,149 @@
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+
+use crate::config::envfile::{EnvFiles, EnvReport};
+use crate::config::overrides;
+use crate::errors::ConfigError;
+
+/// Variable selecting the active profile, overridden by a `--profile <name>` flag
+pub const PROFILE_VAR: &str = "BUSYLIB_PROFILE";
+
+static ACTIVE_PROFILE: Lazy<ArcSwap<Profile>> =
+    Lazy::new(|| ArcSwap::from_pointee(Profile::resolve()));
+
+/// Deployment profile, selects the `.env.<profile>` files and logging defaults
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Profile {
+    Dev,
+    Test,
+    Staging,
+    Prod,
+    Custom(String),
+}
+
+impl Profile {
+    pub fn name(&self) -> &str {
+        match self {
+            Profile::Dev => "dev",
+            Profile::Test => "test",
+            Profile::Staging => "staging",
+            Profile::Prod => "prod",
+            Profile::Custom(name) => name,
+        }
+    }
+
+    /// Resolve from the `--profile` flag, then [`PROFILE_VAR`], defaulting to [`Profile::Prod`]
+    pub fn resolve() -> Self {
+        from_args(env::args().skip(1))
+            .or_else(|| overrides::var(PROFILE_VAR)?.parse().ok())
+            .unwrap_or(Profile::Prod)
+    }
+
+    /// `.env`, `.env.<profile>` and `.env.<profile>.local`, from least to most specific
+    pub fn dotenv_files(&self) -> Vec<String> {
+        vec![
+            ".env".to_string(),
+            format!(".env.{}", self.name()),
+            format!(".env.{}.local", self.name()),
+        ]
+    }
+}
+
+impl FromStr for Profile {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        match name.as_str() {
+            "" => Err(ConfigError {
+                details: "profile name must not be empty".to_string(),
+            }),
+            "dev" | "development" => Ok(Profile::Dev),
+            "test" => Ok(Profile::Test),
+            "staging" => Ok(Profile::Staging),
+            "prod" | "production" => Ok(Profile::Prod),
+            _ => Ok(Profile::Custom(name)),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The profile resolved by [`Profile::resolve`] unless replaced with [`set_active_profile`].
+/// An [`crate::config::EnvOverrides`] of [`PROFILE_VAR`] takes precedence for its scope.
+pub fn active_profile() -> Arc<Profile> {
+    if overrides::is_overridden(PROFILE_VAR) {
+        let profile = overrides::var(PROFILE_VAR).and_then(|name| name.parse().ok());
+        return Arc::new(profile.unwrap_or(Profile::Prod));
+    }
+    ACTIVE_PROFILE.load_full()
+}
+
+pub fn set_active_profile(profile: Profile) {
+    ACTIVE_PROFILE.store(Arc::new(profile));
+}
+
+/// Load the [`Profile::dotenv_files`] of the active profile that exist in the current
+/// directory with [`EnvFiles`]: more specific files override less specific ones, variables
+/// already set in the process env are kept.
+pub fn load_profile_dotenv() -> Result<EnvReport, ConfigError> {
+    active_profile()
+        .dotenv_files()
+        .into_iter()
+        .fold(EnvFiles::new(), EnvFiles::file)
+        .load()
+}
+
+fn from_args(mut args: impl Iterator<Item = String>) -> Option<Profile> {
+    while let Some(arg) = args.next() {
+        if arg == "--profile" {
+            return args.next()?.parse().ok();
+        }
+        if let Some(name) = arg.strip_prefix("--profile=") {
+            return name.parse().ok();
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::profile::{from_args, Profile};
+
+    #[test]
+    fn parse_profile() {
+        assert_eq!("Production".parse::<Profile>().unwrap(), Profile::Prod);
+        assert_eq!("dev".parse::<Profile>().unwrap(), Profile::Dev);
+        assert_eq!(
+            "canary".parse::<Profile>().unwrap(),
+            Profile::Custom("canary".to_string())
+        );
+        assert!("".parse::<Profile>().is_err());
+        assert_eq!(
+            Profile::Staging.dotenv_files(),
+            vec![".env", ".env.staging", ".env.staging.local"]
+        );
+    }
+
+    #[test]
+    fn profile_from_args() {
+        let args = |args: &[&str]| from_args(args.iter().map(|s| s.to_string()));
+        assert_eq!(args(&["debug"]), None);
+        assert_eq!(args(&["serve", "debug"]), None);
+        assert_eq!(args(&["debug", "--profile", "test"]), Some(Profile::Test));
+        assert_eq!(args(&["serve", "--profile", "test"]), Some(Profile::Test));
+        assert_eq!(
+            args(&["--profile=staging", "serve"]),
+            Some(Profile::Staging)
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{error, warn};
+use reqwest::header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::config::watch::{apply, flatten, Validator, WatchEntry};
+use crate::config::{Reloadable, Source};
+use crate::errors::ConfigError;
+use crate::http::client::{default_reqwest_client, ReqwestClient};
+
+/// Polls a JSON or TOML document over HTTP and swaps its values into the registered settings.
+///
+/// Requests carry the last `ETag` in `If-None-Match`, so an unchanged document costs a `304`.
+/// Nested keys are flattened like in [`crate::config::ConfigWatcher`] (`db.host`), values are
+/// decrypted and validated the same way, and nothing is swapped if any value is invalid.
+/// With [`RemoteConfig::cache_file`] the last applied document is kept on disk and used when
+/// the endpoint is unreachable at startup.
+///
+/// ```rust,ignore
+/// static HOST: GlobalString = Lazy::new(|| env_var_with_default("HOST", "127.0.0.1"));
+///
+/// RemoteConfig::new("https://config.internal/my_app.json")
+///     .cache_file("/var/cache/my_app/remote.json")
+///     .register("HOST", &*HOST)
+///     .spawn();
+/// ```
+pub struct RemoteConfig {
+    url: String,
+    client: ReqwestClient,
+    interval: Duration,
+    cache_file: Option<PathBuf>,
+    entries: Vec<WatchEntry>,
+    etag: Mutex<Option<String>>,
+}
+
+impl RemoteConfig {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            client: default_reqwest_client(),
+            interval: Duration::from_secs(30),
+            cache_file: None,
+            entries: Vec::new(),
+            etag: Mutex::new(None),
+        }
+    }
+
+    /// Replace [`default_reqwest_client`], e.g. to add authentication headers
+    pub fn client(mut self, client: ReqwestClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// How often the endpoint is polled, 30 seconds by default
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Where the last applied values are kept for cold starts
+    pub fn cache_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cache_file = Some(path.into());
+        self
+    }
+
+    pub fn register(mut self, key: &str, target: &'static dyn Reloadable) -> Self {
+        self.entries.push(WatchEntry {
+            key: key.to_string(),
+            target,
+            validator: None,
+        });
+        self
+    }
+
+    /// Like [`RemoteConfig::register`], new values are only applied when `validator` accepts them
+    pub fn register_with_validator<F>(
+        mut self,
+        key: &str,
+        target: &'static dyn Reloadable,
+        validator: F,
+    ) -> Self
+    where
+        F: Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    {
+        let validator: Validator = Box::new(validator);
+        self.entries.push(WatchEntry {
+            key: key.to_string(),
+            target,
+            validator: Some(validator),
+        });
+        self
+    }
+
+    /// Fetch the document now and return the number of settings that changed,
+    /// `0` if it was not modified since the last poll
+    pub async fn poll(&self) -> Result<usize, ConfigError> {
+        let mut request = self.client.get(&self.url);
+        if let Some(etag) = self.etag.lock().ok().and_then(|etag| etag.clone()) {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let response = request.send().await.map_err(|e| self.error(e))?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(0);
+        }
+        if !response.status().is_success() {
+            return Err(self.error(format!("unexpected status {}", response.status())));
+        }
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let etag = header(ETAG);
+        let toml = header(CONTENT_TYPE).is_some_and(|t| t.contains("toml"))
+            || self
+                .url
+                .split('?')
+                .next()
+                .unwrap_or_default()
+                .ends_with(".toml");
+        let body = response.text().await.map_err(|e| self.error(e))?;
+
+        let parsed: Result<Value, String> = if toml {
+            toml::from_str(&body).map_err(|e| e.to_string())
+        } else {
+            serde_json::from_str(&body).map_err(|e| e.to_string())
+        };
+        let Value::Object(document) = parsed.map_err(|e| self.error(e))? else {
+            return Err(self.error("the document must contain a table at top level"));
+        };
+        let mut values = HashMap::new();
+        for (key, value) in &document {
+            flatten(key, value, &mut values);
+        }
+
+        let changed = self.apply(&values)?;
+        if let Ok(mut current) = self.etag.lock() {
+            *current = etag;
+        }
+        if let Err(e) = self.save(&values) {
+            warn!("{}", e);
+        }
+        Ok(changed)
+    }
+
+    /// Apply the values cached by the last successful [`RemoteConfig::poll`]
+    pub fn load_cache(&self) -> Result<usize, ConfigError> {
+        let Some(path) = &self.cache_file else {
+            return Err(self.error("no cache file configured"));
+        };
+        let content = fs::read_to_string(path).map_err(|e| ConfigError {
+            details: format!("failed to read {}: {}", path.display(), e),
+        })?;
+        let values: HashMap<String, String> =
+            serde_json::from_str(&content).map_err(|e| ConfigError {
+                details: format!("failed to parse {}: {}", path.display(), e),
+            })?;
+        self.apply(&values)
+    }
+
+    /// [`RemoteConfig::poll`], falling back to [`RemoteConfig::load_cache`] if it fails.
+    /// The poll error is returned when there is no cache either.
+    pub async fn init(&self) -> Result<usize, ConfigError> {
+        match self.poll().await {
+            Ok(changed) => Ok(changed),
+            Err(e) if self.cache_file.as_ref().is_some_and(|p| p.exists()) => {
+                warn!("{}, using the cached snapshot", e);
+                self.load_cache()
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// [`RemoteConfig::init`], then poll every `interval`, logging errors
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            if let Err(e) = self.init().await {
+                error!("{}", e);
+            }
+            let mut ticker = tokio::time::interval(self.interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.poll().await {
+                    error!("{}", e);
+                }
+            }
+        })
+    }
+
+    fn apply(&self, values: &HashMap<String, String>) -> Result<usize, ConfigError> {
+        let source = Source::Remote(self.url.clone());
+        let values = values
+            .iter()
+            .map(|(key, value)| (key.clone(), (value.clone(), source.clone())))
+            .collect();
+        apply(&self.entries, &values).map(|changes| changes.len())
+    }
+
+    /// write the values as fetched to the cache file through a temporary file readable by the
+    /// owner only, so a crash never leaves a truncated snapshot behind. `ENC(...)` values stay
+    /// encrypted, but secrets the server sends in clear are cached in clear.
+    fn save(&self, values: &HashMap<String, String>) -> Result<(), ConfigError> {
+        let Some(path) = &self.cache_file else {
+            return Ok(());
+        };
+        let failed = |e: std::io::Error| ConfigError {
+            details: format!("failed to write {}: {}", path.display(), e),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(failed)?;
+        }
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".tmp");
+        let tmp = path.with_file_name(name);
+        // a leftover of a crash may have looser permissions
+        let _ = fs::remove_file(&tmp);
+        let content = serde_json::to_string_pretty(values).unwrap_or_default();
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&tmp)
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.sync_all()
+            })
+            .map_err(failed)?;
+        fs::rename(&tmp, path).map_err(failed)
+    }
+
+    fn error(&self, e: impl std::fmt::Display) -> ConfigError {
+        ConfigError {
+            details: format!("remote config {}: {}", self.url, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use once_cell::sync::Lazy;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::config::{env_var_with_default, GlobalString, RemoteConfig};
+
+    static REMOTE_HOST: GlobalString =
+        Lazy::new(|| env_var_with_default("REMOTE_TEST_HOST", "127.0.0.1"));
+    static REMOTE_PORT: GlobalString =
+        Lazy::new(|| env_var_with_default("REMOTE_TEST_PORT", "8080"));
+
+    /// serve the document with an etag, `304` when it matches and `503` after `up` requests
+    async fn serve(up: usize) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/app.json", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buffer = vec![0; 4096];
+                let read = stream.read(&mut buffer).await.unwrap();
+                let request = String::from_utf8_lossy(&buffer[..read]).to_lowercase();
+                let body = r#"{"remote_test": {"host": "10.0.0.1", "port": 9090}}"#;
+                let response = if counter.fetch_add(1, Ordering::SeqCst) >= up {
+                    "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_string()
+                } else if request.contains("if-none-match: \"v1\"") {
+                    "HTTP/1.1 304 Not Modified\r\netag: \"v1\"\r\nconnection: close\r\n\r\n"
+                        .to_string()
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\netag: \"v1\"\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.ok();
+            }
+        });
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn poll_and_cache() {
+        let cache = env::temp_dir().join("busylib_remote_test/snapshot.json");
+        fs::remove_file(&cache).ok();
+        let (url, requests) = serve(2).await;
+
+        let remote = RemoteConfig::new(&url)
+            .cache_file(&cache)
+            .register("remote_test.host", &*REMOTE_HOST)
+            .register_with_validator("remote_test.port", &*REMOTE_PORT, |v| {
+                v.parse::<u16>().map(|_| ()).map_err(|e| e.to_string())
+            });
+        assert_eq!(remote.poll().await.unwrap(), 2);
+        assert_eq!(REMOTE_HOST.load().as_str(), "10.0.0.1");
+        assert_eq!(REMOTE_PORT.load().as_str(), "9090");
+        assert_eq!(remote.poll().await.unwrap(), 0);
+        assert!(remote.poll().await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&cache).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // cold start while the endpoint is down
+        REMOTE_HOST.store(Arc::new("127.0.0.1".to_string()));
+        let cold = RemoteConfig::new(&url)
+            .cache_file(&cache)
+            .register("remote_test.host", &*REMOTE_HOST);
+        assert_eq!(cold.init().await.unwrap(), 1);
+        assert_eq!(REMOTE_HOST.load().as_str(), "10.0.0.1");
+    }
+}
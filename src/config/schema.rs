@@ -0,0 +1,362 @@
+//! Registry of the configuration keys an application reads, exported as a JSON Schema, a
+//! Markdown table or a `.env.example` file.
+//!
+//! The getters of the config module and [`crate::config::ConfigLoader`] register every key
+//! they read with its type and default. Typed defaults are only recorded by
+//! [`crate::config::env_parse_documented`] and [`crate::config::EnvCollector::parse_documented`],
+//! as they need `Display`. [`declare`] adds descriptions or keys that are not read at startup.
+//!
+//! ```rust,ignore
+//! declare(ConfigKey::new("DB_HOST").description("database host name"));
+//! let host = env_string_with_default("DB_HOST", "localhost");
+//! fs::write(".env.example", env_example())?;
+//! fs::write("config.schema.json", json_schema().to_string())?;
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::config::dump::is_secret;
+
+static KEYS: Lazy<Mutex<BTreeMap<String, ConfigKey>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// JSON Schema type of a key
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    Array,
+    Object,
+}
+
+impl KeyType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyType::String => "string",
+            KeyType::Integer => "integer",
+            KeyType::Number => "number",
+            KeyType::Boolean => "boolean",
+            KeyType::Array => "array",
+            KeyType::Object => "object",
+        }
+    }
+
+    /// the type of a default value of the config loader
+    pub(crate) fn of_value(value: &Value) -> Self {
+        match value {
+            Value::Bool(_) => KeyType::Boolean,
+            Value::Number(n) if n.is_f64() => KeyType::Number,
+            Value::Number(_) => KeyType::Integer,
+            Value::Array(_) => KeyType::Array,
+            Value::Object(_) => KeyType::Object,
+            Value::String(_) | Value::Null => KeyType::String,
+        }
+    }
+
+    /// the type of a value parsed into `T`, by its short type name
+    pub(crate) fn of_type(short_name: &str) -> Self {
+        match short_name {
+            "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64"
+            | "i128" | "isize" => KeyType::Integer,
+            "f32" | "f64" => KeyType::Number,
+            "bool" => KeyType::Boolean,
+            _ => KeyType::String,
+        }
+    }
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A declared configuration key, named as its environment variable
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConfigKey {
+    pub name: String,
+    pub kind: KeyType,
+    pub default: Option<Value>,
+    pub required: bool,
+    /// always set for names matching [`crate::config::dump::is_secret`]
+    pub secret: bool,
+    pub description: Option<String>,
+}
+
+impl ConfigKey {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: KeyType::String,
+            default: None,
+            required: false,
+            secret: is_secret(name),
+            description: None,
+        }
+    }
+
+    pub fn kind(mut self, kind: KeyType) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn default(mut self, value: impl Into<Value>) -> Self {
+        self.default = Some(value.into());
+        self
+    }
+
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    pub fn secret(mut self, secret: bool) -> Self {
+        self.secret = secret || is_secret(&self.name);
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    /// the default as written in `.env`, never shown for secrets
+    fn default_text(&self) -> Option<String> {
+        match &self.default {
+            _ if self.secret => None,
+            Some(Value::String(s)) => Some(s.clone()),
+            Some(other) => Some(other.to_string()),
+            None => None,
+        }
+    }
+}
+
+/// Declare a key or complete an already registered one. A [`KeyType::String`] kind is taken
+/// as unspecified and does not replace a more specific kind.
+pub fn declare(key: ConfigKey) {
+    if let Ok(mut keys) = KEYS.lock() {
+        let merged = match keys.remove(&key.name) {
+            Some(known) => ConfigKey {
+                kind: specific(key.kind, known.kind),
+                default: key.default.or(known.default),
+                required: key.required || known.required,
+                secret: key.secret || known.secret,
+                description: key.description.or(known.description),
+                ..key
+            },
+            None => key,
+        };
+        keys.insert(merged.name.clone(), merged);
+    }
+}
+
+/// register a key read by a getter, keeping what was declared explicitly
+pub(crate) fn register(name: &str, kind: KeyType, default: Option<Value>, required: bool) {
+    if let Ok(mut keys) = KEYS.lock() {
+        let key = keys
+            .entry(name.to_string())
+            .or_insert_with(|| ConfigKey::new(name));
+        key.kind = specific(key.kind, kind);
+        key.default = key.default.take().or(default);
+        key.required |= required;
+    }
+}
+
+fn specific(kind: KeyType, fallback: KeyType) -> KeyType {
+    if kind == KeyType::String {
+        fallback
+    } else {
+        kind
+    }
+}
+
+/// Every declared or registered key, sorted by name
+pub fn keys() -> Vec<ConfigKey> {
+    KEYS.lock()
+        .map(|keys| keys.values().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// A JSON Schema (draft 2020-12) of the environment, secrets are marked `writeOnly` and
+/// their defaults omitted
+pub fn json_schema() -> Value {
+    let keys = keys();
+    let mut properties = Map::new();
+    for key in &keys {
+        let mut property = Map::new();
+        property.insert("type".to_string(), json!(key.kind.name()));
+        if let Some(description) = &key.description {
+            property.insert("description".to_string(), json!(description));
+        }
+        if let (Some(default), false) = (&key.default, key.secret) {
+            property.insert("default".to_string(), default.clone());
+        }
+        if key.secret {
+            property.insert("writeOnly".to_string(), json!(true));
+        }
+        properties.insert(key.name.clone(), Value::Object(property));
+    }
+    let required: Vec<&str> = keys
+        .iter()
+        .filter(|k| k.required)
+        .map(|k| k.name.as_str())
+        .collect();
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+/// A Markdown table with one row per key
+pub fn markdown() -> String {
+    let mut doc = String::from(
+        "| Variable | Type | Default | Required | Secret | Description |\n\
+         |---|---|---|---|---|---|\n",
+    );
+    for key in keys() {
+        let default = key
+            .default_text()
+            .map(|d| format!("`{}`", d))
+            .unwrap_or_default();
+        doc.push_str(&format!(
+            "| `{}` | {} | {} | {} | {} | {} |\n",
+            key.name,
+            key.kind,
+            default.replace('|', "\\|"),
+            if key.required { "yes" } else { "no" },
+            if key.secret { "yes" } else { "no" },
+            key.description.unwrap_or_default().replace('|', "\\|"),
+        ));
+    }
+    doc
+}
+
+/// A `.env.example` assigning every key its default, preceded by a comment with its
+/// description and type
+pub fn env_example() -> String {
+    let mut example = String::new();
+    for key in keys() {
+        if let Some(description) = &key.description {
+            example.push_str(&format!("# {}\n", description));
+        }
+        let mut notes = vec![key.kind.name()];
+        if key.required {
+            notes.push("required");
+        }
+        if key.secret {
+            notes.push("secret");
+        }
+        example.push_str(&format!("# {}\n", notes.join(", ")));
+        let value = key.default_text().unwrap_or_default();
+        if value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '#' || c == '$')
+        {
+            example.push_str(&format!("{}='{}'\n\n", key.name, value));
+        } else {
+            example.push_str(&format!("{}={}\n\n", key.name, value));
+        }
+    }
+    example
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+    use std::path::PathBuf;
+
+    use serde::Serialize;
+    use serde_json::json;
+
+    use crate::config::schema::{declare, env_example, json_schema, keys, markdown, ConfigKey};
+    use crate::config::{
+        env_parse, env_parse_documented, env_required, env_string_with_default, ConfigLoader,
+        EnvCollector,
+    };
+
+    #[derive(Serialize, serde::Deserialize)]
+    struct Db {
+        host: String,
+        pool_size: u32,
+    }
+
+    #[derive(Serialize, serde::Deserialize)]
+    struct App {
+        debug: bool,
+        db: Db,
+    }
+
+    #[test]
+    fn export_declared_keys() {
+        declare(ConfigKey::new("SCHEMA_TEST_HOST").description("public host name"));
+        env_string_with_default("SCHEMA_TEST_HOST", "localhost");
+        env_string_with_default("SCHEMA_TEST_API_TOKEN", "dev-token");
+        env_parse_documented("SCHEMA_TEST_WORKERS", 4usize).unwrap();
+        env_parse("SCHEMA_TEST_DATA_DIR", PathBuf::from("/var/lib/app")).unwrap();
+        let mut collector = EnvCollector::new();
+        let _ratio: f64 = collector.parse_documented("SCHEMA_TEST_BACKOFF", 1.5);
+        let _bind: IpAddr = collector.parse_documented("SCHEMA_TEST_BIND", [0, 0, 0, 0].into());
+        let _retries: u8 = collector.parse("SCHEMA_TEST_RETRIES", 3);
+        let _ = env_required::<f64>("SCHEMA_TEST_RATIO");
+        let _ = ConfigLoader::new()
+            .dotenv(false)
+            .env_prefix("SCHEMATEST")
+            .defaults(&App {
+                debug: false,
+                db: Db {
+                    host: "db | primary".to_string(),
+                    pool_size: 8,
+                },
+            })
+            .load::<App>();
+
+        let keys = keys();
+        let key = |name: &str| keys.iter().find(|k| k.name == name).unwrap();
+        assert_eq!(
+            key("SCHEMA_TEST_HOST").description.as_deref(),
+            Some("public host name")
+        );
+        assert!(key("SCHEMA_TEST_API_TOKEN").secret);
+        assert!(key("SCHEMA_TEST_RATIO").required);
+        assert_eq!(key("SCHEMATEST__DB__POOL_SIZE").default, Some(json!(8)));
+
+        let schema = json_schema();
+        let property = |name: &str| &schema["properties"][name];
+        assert_eq!(property("SCHEMA_TEST_HOST")["default"], "localhost");
+        assert_eq!(property("SCHEMA_TEST_WORKERS")["type"], "integer");
+        assert_eq!(property("SCHEMA_TEST_WORKERS")["default"], json!(4));
+        assert_eq!(property("SCHEMA_TEST_BACKOFF")["default"], json!(1.5));
+        assert_eq!(property("SCHEMA_TEST_BIND")["default"], "0.0.0.0");
+        assert!(property("SCHEMA_TEST_RATIO").get("default").is_none());
+        assert!(property("SCHEMA_TEST_DATA_DIR").get("default").is_none());
+        assert!(property("SCHEMA_TEST_RETRIES").get("default").is_none());
+        assert_eq!(property("SCHEMA_TEST_RATIO")["type"], "number");
+        assert_eq!(property("SCHEMATEST__DEBUG")["type"], "boolean");
+        assert_eq!(property("SCHEMA_TEST_API_TOKEN")["writeOnly"], true);
+        assert!(property("SCHEMA_TEST_API_TOKEN").get("default").is_none());
+        assert!(schema["required"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("SCHEMA_TEST_RATIO")));
+
+        let example = env_example();
+        assert!(example.contains("# public host name\n# string\nSCHEMA_TEST_HOST=localhost\n"));
+        assert!(example.contains("# string, secret\nSCHEMA_TEST_API_TOKEN=\n"));
+        assert!(example.contains("SCHEMATEST__DB__HOST='db | primary'\n"));
+        assert!(example.contains("# integer\nSCHEMA_TEST_WORKERS=4\n"));
+        assert!(example.contains("# string\nSCHEMA_TEST_BIND=0.0.0.0\n"));
+        assert!(markdown().contains("| `SCHEMA_TEST_WORKERS` | integer | `4` | no | no |  |\n"));
+        assert!(markdown().contains("| `SCHEMA_TEST_RATIO` | number |  | yes | no |  |\n"));
+        assert!(markdown().contains("`db \\| primary`"));
+    }
+}
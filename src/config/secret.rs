@@ -0,0 +1,126 @@
+use std::fs;
+
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+
+use crate::config::overrides;
+use crate::crypto::{encrypt_by_key, Keyring};
+use crate::errors::{DecryptError, EnvDecryptError};
+
+/// Variable holding the master key used to decrypt `ENC(...)` values
+pub const MASTER_KEY_VAR: &str = "BUSYLIB_MASTER_KEY";
+/// Variable holding the path of a file containing the master key
+pub const MASTER_KEY_FILE_VAR: &str = "BUSYLIB_MASTER_KEY_FILE";
+
+static MASTER_KEY: Lazy<ArcSwap<Option<String>>> = Lazy::new(|| ArcSwap::from_pointee(None));
+static MASTER_KEYRING: Lazy<ArcSwap<Option<Keyring>>> = Lazy::new(|| ArcSwap::from_pointee(None));
+
+/// Set the master key explicitly, it takes precedence over [`MASTER_KEY_VAR`] and
+/// [`MASTER_KEY_FILE_VAR`]
+pub fn set_master_key(key: &str) {
+    MASTER_KEY.store(std::sync::Arc::new(Some(key.to_string())));
+}
+
+/// Resolve the master key from [`set_master_key`], [`MASTER_KEY_VAR`] or the file named by
+/// [`MASTER_KEY_FILE_VAR`], in that order
+pub fn master_key() -> Result<String, DecryptError> {
+    if let Some(key) = MASTER_KEY.load().as_ref() {
+        return Ok(key.clone());
+    }
+    if let Some(key) = overrides::var(MASTER_KEY_VAR) {
+        return Ok(key);
+    }
+    if let Some(path) = overrides::var(MASTER_KEY_FILE_VAR) {
+        return fs::read_to_string(&path)
+            .map(|key| key.trim_end_matches(['\r', '\n']).to_string())
+            .map_err(|e| DecryptError {
+                details: format!("failed to read master key file {}: {}", path, e),
+            });
+    }
+    Err(DecryptError {
+        details: format!(
+            "master key not set, use {} or {}",
+            MASTER_KEY_VAR, MASTER_KEY_FILE_VAR
+        ),
+    })
+}
+
+/// Decrypt `ENC(...)` values with `keyring` instead of the master key, so values encrypted
+/// with rotated keys keep working
+pub fn set_master_keyring(keyring: Keyring) {
+    MASTER_KEYRING.store(std::sync::Arc::new(Some(keyring)));
+}
+
+/// The keyring set by [`set_master_keyring`], or a keyring holding [`master_key`] as the
+/// unnamed key used by [`crate::crypto::encrypt_by_key`]
+pub fn master_keyring() -> Result<Keyring, DecryptError> {
+    if let Some(keyring) = MASTER_KEYRING.load().as_ref() {
+        return Ok(keyring.clone());
+    }
+    master_key().map(|key| Keyring::new("", &key))
+}
+
+/// whether `value` is an `ENC(<base64>)` marker
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with("ENC(") && value.ends_with(')')
+}
+
+/// Wrap the encrypted `value` in an `ENC(...)` marker
+pub fn encrypt_value(value: &str, key: &str) -> String {
+    format!("ENC({})", encrypt_by_key(value.to_string(), key))
+}
+
+/// Decrypt `value` of the variable `name` with the [`master_keyring`] if it is an `ENC(...)`
+/// marker, return it unchanged otherwise
+pub fn decrypt_value(name: &str, value: String) -> Result<String, EnvDecryptError> {
+    if !is_encrypted(&value) {
+        return Ok(value);
+    }
+    let to_error = |source| EnvDecryptError {
+        name: name.to_string(),
+        source,
+    };
+    let keyring = master_keyring().map_err(to_error)?;
+    keyring
+        .decrypt(&value[4..value.len() - 1])
+        .map_err(to_error)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::secret::{decrypt_value, encrypt_value, MASTER_KEY_VAR};
+    use crate::config::{env_parse, env_string_with_default, env_string_with_error, EnvOverrides};
+    use crate::errors::EnvIssueKind;
+
+    #[test]
+    fn decrypt_enc_values() {
+        let _guard = EnvOverrides::new()
+            .set(MASTER_KEY_VAR, "master")
+            .set("SECRET_TEST_PASSWORD", &encrypt_value("s3cret", "master"))
+            .set("SECRET_TEST_PORT", &encrypt_value("5432", "master"))
+            .set("SECRET_TEST_BROKEN", &encrypt_value("s3cret", "other"))
+            .enter();
+
+        assert_eq!(
+            env_string_with_default("SECRET_TEST_PASSWORD", ""),
+            "s3cret"
+        );
+        assert_eq!(env_parse("SECRET_TEST_PORT", 0u16).unwrap(), 5432);
+        assert_eq!(
+            decrypt_value("PLAIN", "plain".to_string()).unwrap(),
+            "plain"
+        );
+
+        let err = env_string_with_error("SECRET_TEST_BROKEN", "").unwrap_err();
+        assert_eq!(err.name(), "SECRET_TEST_BROKEN");
+        assert_eq!(
+            env_string_with_default("SECRET_TEST_BROKEN", "none"),
+            "none"
+        );
+        let err = env_parse("SECRET_TEST_BROKEN", String::new()).unwrap_err();
+        assert!(matches!(
+            err.issues()[0].kind,
+            EnvIssueKind::Undecryptable(_)
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+use std::sync::Arc;
+
+use arc_swap::{ArcSwap, Guard};
+use once_cell::sync::Lazy;
+use tokio::sync::watch;
+
+use crate::config::Reloadable;
+
+pub type GlobalSetting = Lazy<Setting>;
+
+type Callback = dyn Fn(&str, &str) + Send + Sync;
+
+/// A lock-free string setting like [`crate::config::GlobalString`] that notifies subscribers
+/// whenever its value is replaced.
+///
+/// ```rust,ignore
+/// static LOG_LEVEL: GlobalSetting = Lazy::new(|| setting_with_default("LOG_LEVEL", "info"));
+///
+/// LOG_LEVEL.on_change(move |_old, new| {
+///     if let Ok(level) = new.parse() {
+///         change_log_level(&handle, &["my_app"], level);
+///     }
+/// });
+/// let mut timeout = HTTP_TIMEOUT.subscribe();
+/// while timeout.changed().await.is_ok() {
+///     rebuild_client(timeout.borrow().as_str());
+/// }
+/// ```
+pub struct Setting {
+    value: ArcSwap<String>,
+    sender: watch::Sender<Arc<String>>,
+    callbacks: ArcSwap<Vec<Arc<Callback>>>,
+}
+
+impl Setting {
+    pub fn new(value: impl Into<String>) -> Self {
+        let value = Arc::new(value.into());
+        Self {
+            sender: watch::channel(value.clone()).0,
+            value: ArcSwap::new(value),
+            callbacks: ArcSwap::from_pointee(Vec::new()),
+        }
+    }
+
+    pub fn load(&self) -> Guard<Arc<String>> {
+        self.value.load()
+    }
+
+    pub fn load_full(&self) -> Arc<String> {
+        self.value.load_full()
+    }
+
+    /// Replace the value and notify subscribers, nothing happens if the value is unchanged
+    pub fn store(&self, value: impl Into<String>) {
+        self.replace(Arc::new(value.into()));
+    }
+
+    /// A receiver seeing every new value, see [`watch::Receiver::changed`]
+    pub fn subscribe(&self) -> watch::Receiver<Arc<String>> {
+        self.sender.subscribe()
+    }
+
+    /// Call `callback` with the old and new value after every replacement
+    pub fn on_change<F>(&self, callback: F)
+    where
+        F: Fn(&str, &str) + Send + Sync + 'static,
+    {
+        let callback: Arc<Callback> = Arc::new(callback);
+        self.callbacks.rcu(|callbacks| {
+            let mut callbacks = callbacks.as_ref().clone();
+            callbacks.push(callback.clone());
+            callbacks
+        });
+    }
+}
+
+impl Reloadable for Setting {
+    fn current(&self) -> Arc<String> {
+        self.load_full()
+    }
+
+    fn replace(&self, value: Arc<String>) {
+        let old = self.value.swap(value.clone());
+        if old == value {
+            return;
+        }
+        self.sender.send_replace(value.clone());
+        for callback in self.callbacks.load().iter() {
+            callback(&old, &value);
+        }
+    }
+}
+
+impl fmt::Debug for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Setting").field(&self.load_full()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use once_cell::sync::Lazy;
+
+    use crate::config::{setting_with_default, ConfigWatcher, GlobalSetting, Setting};
+
+    static TIMEOUT: GlobalSetting =
+        Lazy::new(|| setting_with_default("SETTING_TEST_TIMEOUT", "10"));
+
+    #[tokio::test]
+    async fn subscribe_and_callback() {
+        let setting = Setting::new("info");
+        let mut receiver = setting.subscribe();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        setting.on_change(move |old, new| {
+            assert_eq!((old, new), ("info", "debug"));
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        setting.store("debug");
+        setting.store("debug");
+        receiver.changed().await.unwrap();
+        assert_eq!(receiver.borrow_and_update().as_str(), "debug");
+        assert_eq!(setting.load().as_str(), "debug");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn notify_on_reload() {
+        let dir = env::temp_dir().join("busylib_setting_test");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("timeout.env");
+        fs::write(&file, "SETTING_TEST_TIMEOUT=30\n").unwrap();
+
+        let mut receiver = TIMEOUT.subscribe();
+        ConfigWatcher::new()
+            .file(&file)
+            .register("SETTING_TEST_TIMEOUT", &*TIMEOUT)
+            .reload()
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), receiver.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receiver.borrow().as_str(), "30");
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use arc_swap::ArcSwap;
+use log::{error, info};
+use serde_json::Value;
+
+use crate::config::dump::{self, mask};
+use crate::config::envfile::parse_file;
+use crate::config::loader::read_file;
+use crate::config::secret::{decrypt_value, is_encrypted};
+use crate::config::Source;
+use crate::errors::ConfigError;
+
+/// A setting whose value can be swapped at runtime, e.g. a [`crate::config::GlobalString`]
+pub trait Reloadable: Send + Sync {
+    fn current(&self) -> Arc<String>;
+
+    fn replace(&self, value: Arc<String>);
+}
+
+impl Reloadable for ArcSwap<String> {
+    fn current(&self) -> Arc<String> {
+        self.load_full()
+    }
+
+    fn replace(&self, value: Arc<String>) {
+        self.store(value)
+    }
+}
+
+pub(crate) type Validator = Box<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+pub(crate) struct WatchEntry {
+    pub(crate) key: String,
+    pub(crate) target: &'static dyn Reloadable,
+    pub(crate) validator: Option<Validator>,
+}
+
+/// Re-reads `.env`/config files when they change and swaps the new values into the
+/// registered settings.
+///
+/// Files are read in the order they were added, later files overriding earlier ones.
+/// `.toml` and `.json` files are flattened to dotted keys (`db.host`), any other file is
+/// read as a `.env` file. A registered key missing from every file keeps its current value.
+/// `ENC(...)` values are decrypted with [`crate::config::secret::master_key`].
+/// When any new value fails decryption or validation nothing is swapped.
+/// Only a [`crate::config::Setting`] notifies subscribers of the swap, a
+/// [`crate::config::GlobalString`] is replaced silently.
+///
+/// ```rust,ignore
+/// static HOST: GlobalString = Lazy::new(|| env_var_with_default("HOST", "127.0.0.1"));
+///
+/// ConfigWatcher::new()
+///     .file(".env")
+///     .register("HOST", &*HOST)
+///     .spawn();
+/// ```
+pub struct ConfigWatcher {
+    files: Vec<PathBuf>,
+    entries: Vec<WatchEntry>,
+    interval: Duration,
+}
+
+impl Default for ConfigWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConfigWatcher {
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            entries: Vec::new(),
+            interval: Duration::from_secs(5),
+        }
+    }
+
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.files.push(path.into());
+        self
+    }
+
+    /// How often files are polled for changes, 5 seconds by default
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn register(mut self, key: &str, target: &'static dyn Reloadable) -> Self {
+        self.entries.push(WatchEntry {
+            key: key.to_string(),
+            target,
+            validator: None,
+        });
+        self
+    }
+
+    /// Like [`ConfigWatcher::register`], new values are only applied when `validator` accepts them
+    pub fn register_with_validator<F>(
+        mut self,
+        key: &str,
+        target: &'static dyn Reloadable,
+        validator: F,
+    ) -> Self
+    where
+        F: Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.entries.push(WatchEntry {
+            key: key.to_string(),
+            target,
+            validator: Some(Box::new(validator)),
+        });
+        self
+    }
+
+    /// Re-read all files now and return the number of settings that changed
+    pub fn reload(&self) -> Result<usize, ConfigError> {
+        let mut values = HashMap::new();
+        for path in self.files.iter().filter(|p| p.exists()) {
+            for (key, value) in read_values(path)? {
+                values.insert(key, (value, source_of(path)));
+            }
+        }
+        apply(&self.entries, &values).map(|changes| changes.len())
+    }
+
+    /// Poll the files every `interval` and reload when any of them was modified
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        let files = self.files.clone();
+        let interval = self.interval;
+        poll_files(files, interval, move || self.reload().map(|_| ()))
+    }
+}
+
+/// Call `reload` every time one of `files` is modified, created or removed, logging its errors
+pub(crate) fn poll_files<F>(
+    files: Vec<PathBuf>,
+    interval: Duration,
+    reload: F,
+) -> tokio::task::JoinHandle<()>
+where
+    F: Fn() -> Result<(), ConfigError> + Send + 'static,
+{
+    let stamps = move || -> Vec<Option<(SystemTime, u64)>> {
+        files
+            .iter()
+            .map(|p| {
+                let metadata = fs::metadata(p).ok()?;
+                Some((metadata.modified().ok()?, metadata.len()))
+            })
+            .collect()
+    };
+    tokio::spawn(async move {
+        let mut last = stamps();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let current = stamps();
+            if current == last {
+                continue;
+            }
+            last = current;
+            if let Err(e) = reload() {
+                error!("{}", e);
+            }
+        }
+    })
+}
+
+/// Decrypt and validate the new values of `entries`, then swap them in if all are valid.
+/// Entries missing from `values` keep their current value. Returns the logged changes, with
+/// decrypted and secret values masked.
+pub(crate) fn apply(
+    entries: &[WatchEntry],
+    values: &HashMap<String, (String, Source)>,
+) -> Result<Vec<String>, ConfigError> {
+    let mut changes = Vec::new();
+    let mut invalid = Vec::new();
+    for entry in entries {
+        let Some((raw, source)) = values.get(&entry.key) else {
+            continue;
+        };
+        let new = match decrypt_value(&entry.key, raw.clone()) {
+            Ok(new) => new,
+            Err(e) => {
+                invalid.push(e.to_string());
+                continue;
+            }
+        };
+        if entry.target.current().as_str() == new {
+            continue;
+        }
+        if let Some(validator) = &entry.validator {
+            if let Err(e) = validator(&new) {
+                invalid.push(format!("{}: {}", entry.key, e));
+                continue;
+            }
+        }
+        changes.push((entry, new, is_encrypted(raw), source));
+    }
+
+    if !invalid.is_empty() {
+        return Err(ConfigError {
+            details: format!(
+                "config reload rolled back, invalid values: {}",
+                invalid.join(", ")
+            ),
+        });
+    }
+    let mut logged = Vec::with_capacity(changes.len());
+    for (entry, new, decrypted, source) in &changes {
+        let old = entry.target.current();
+        let secret =
+            *decrypted || dump::was_secret(&entry.key) || matches!(source, Source::SecretFile(_));
+        entry.target.replace(Arc::new(new.clone()));
+        dump::record(&entry.key, Some(new), (*source).clone(), *decrypted);
+        let line = format!(
+            "config `{}` changed: {:?} -> {:?}",
+            entry.key,
+            mask(&entry.key, &old, secret),
+            mask(&entry.key, new, secret)
+        );
+        info!("{}", line);
+        logged.push(line);
+    }
+    Ok(logged)
+}
+
+fn source_of(path: &Path) -> Source {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") | Some("json") => Source::File(path.to_path_buf()),
+        _ => Source::DotEnv(path.to_path_buf()),
+    }
+}
+
+fn read_values(path: &Path) -> Result<HashMap<String, String>, ConfigError> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") | Some("json") => {
+            let mut values = HashMap::new();
+            for (key, value) in read_file(path)? {
+                flatten(&key, &value, &mut values);
+            }
+            Ok(values)
+        }
+        _ => parse_file(path),
+    }
+}
+
+pub(crate) fn flatten(path: &str, value: &Value, values: &mut HashMap<String, String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                flatten(&format!("{}.{}", path, key), value, values);
+            }
+        }
+        Value::String(s) => {
+            values.insert(path.to_string(), s.clone());
+        }
+        other => {
+            values.insert(path.to_string(), other.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::time::Duration;
+
+    use once_cell::sync::Lazy;
+
+    use crate::config::secret::{encrypt_value, MASTER_KEY_VAR};
+    use crate::config::watch::apply;
+    use crate::config::{env_var_with_default, ConfigWatcher, EnvOverrides, GlobalString, Source};
+
+    static WATCHED_HOST: GlobalString =
+        Lazy::new(|| env_var_with_default("WATCH_TEST_HOST", "127.0.0.1"));
+    static WATCHED_PORT: GlobalString =
+        Lazy::new(|| env_var_with_default("WATCH_TEST_PORT", "8080"));
+    static WATCHED_NAME: GlobalString =
+        Lazy::new(|| env_var_with_default("WATCH_TEST_NAME", "app"));
+    static WATCHED_DSN: GlobalString =
+        Lazy::new(|| env_var_with_default("WATCH_TEST_DSN", "postgres://localhost"));
+
+    #[tokio::test]
+    async fn reload_on_change() {
+        let dir = env::temp_dir().join("busylib_watch_test");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("reload.env");
+        fs::write(&file, "WATCH_TEST_HOST=127.0.0.1\n").unwrap();
+
+        let handle = ConfigWatcher::new()
+            .file(&file)
+            .interval(Duration::from_millis(20))
+            .register("WATCH_TEST_HOST", &*WATCHED_HOST)
+            .spawn();
+        // rewrite until seen, the first write may land before the watcher took its snapshot
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while WATCHED_HOST.load().as_str() != "10.0.0.1" && tokio::time::Instant::now() < deadline {
+            fs::write(&file, "WATCH_TEST_HOST=10.0.0.1\n").unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        handle.abort();
+
+        assert_eq!(WATCHED_HOST.load().as_str(), "10.0.0.1");
+    }
+
+    #[test]
+    fn rollback_on_invalid_value() {
+        let dir = env::temp_dir().join("busylib_watch_test");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("rollback.toml");
+        fs::write(&file, "WATCH_TEST_NAME = \"svc\"\nWATCH_TEST_PORT = 0\n").unwrap();
+
+        let watcher = ConfigWatcher::new()
+            .file(&file)
+            .register("WATCH_TEST_NAME", &*WATCHED_NAME)
+            .register_with_validator("WATCH_TEST_PORT", &*WATCHED_PORT, |v| {
+                match v.parse::<u16>() {
+                    Ok(port) if port > 0 => Ok(()),
+                    _ => Err(format!("{} is not a valid port", v)),
+                }
+            });
+        assert!(watcher.reload().is_err());
+        assert_eq!(WATCHED_NAME.load().as_str(), "app");
+        assert_eq!(WATCHED_PORT.load().as_str(), "8080");
+
+        fs::write(&file, "WATCH_TEST_NAME = \"svc\"\nWATCH_TEST_PORT = 9090\n").unwrap();
+        assert_eq!(watcher.reload().unwrap(), 2);
+        assert_eq!(WATCHED_NAME.load().as_str(), "svc");
+        assert_eq!(WATCHED_PORT.load().as_str(), "9090");
+    }
+
+    #[test]
+    fn mask_decrypted_changes() {
+        let _guard = EnvOverrides::new().set(MASTER_KEY_VAR, "master").enter();
+        let watcher = ConfigWatcher::new().register("WATCH_TEST_DSN", &*WATCHED_DSN);
+        let values = HashMap::from([(
+            "WATCH_TEST_DSN".to_string(),
+            (
+                encrypt_value("postgres://admin:s3cret@db", "master"),
+                Source::Env,
+            ),
+        )]);
+        let logged = apply(&watcher.entries, &values).unwrap();
+        assert_eq!(WATCHED_DSN.load().as_str(), "postgres://admin:s3cret@db");
+        assert_eq!(
+            logged,
+            vec![r#"config `WATCH_TEST_DSN` changed: "******" -> "******""#]
+        );
+
+        // the old value stays masked once it was decrypted
+        let values = HashMap::from([(
+            "WATCH_TEST_DSN".to_string(),
+            ("postgres://db".to_string(), Source::Env),
+        )]);
+        let logged = apply(&watcher.entries, &values).unwrap();
+        assert_eq!(
+            logged,
+            vec![r#"config `WATCH_TEST_DSN` changed: "******" -> "******""#]
+        );
+    }
+}
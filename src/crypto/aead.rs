@@ -0,0 +1,165 @@
+//! Versioned AES-256-GCM ciphertexts.
+//!
+//! Layout: `MAGIC | version | algorithm | key id | key derivation | salt | nonce | ciphertext`.
+//! The key id is prefixed with its length. The whole header up to and including the nonce is
+//! authenticated as associated data, so changing any of it fails decryption like tampering
+//! with the ciphertext does.
+
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{AeadCore, Aes256Gcm, Key, Nonce};
+
+use crate::crypto::kdf::{KeyDerivation, KEY_LEN, SALT_LEN};
+use crate::errors::DecryptError;
+use crate::prelude::EnhancedExpect;
+
+/// Marks a versioned ciphertext, legacy magic-crypt ciphertexts are raw AES-CBC blocks
+pub(crate) const MAGIC: &[u8; 4] = b"BSYL";
+pub(crate) const VERSION: u8 = 3;
+pub(crate) const ALG_AES_256_GCM: u8 = 1;
+
+pub(crate) const NONCE_LEN: usize = 12;
+
+/// A parsed ciphertext, borrowing from the raw bytes
+pub(crate) struct Sealed<'a> {
+    pub(crate) key_id: &'a str,
+    pub(crate) kdf: KeyDerivation,
+    header: &'a [u8],
+    salt: &'a [u8],
+    nonce: &'a [u8],
+    ciphertext: &'a [u8],
+}
+
+/// whether `data` starts with a versioned header
+pub(crate) fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub(crate) fn cipher(key: &[u8]) -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+}
+
+/// encrypt `plaintext` under a random nonce with a key derived from `key`
+pub(crate) fn seal(plaintext: &[u8], key_id: &str, key: &str, kdf: &KeyDerivation) -> Vec<u8> {
+    let mut header = Vec::with_capacity(64 + key_id.len());
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&[VERSION, ALG_AES_256_GCM]);
+    let cipher = cipher(&write_key_fields(&mut header, key_id, key, kdf));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    header.extend_from_slice(&nonce);
+
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: &header,
+            },
+        )
+        .ex("AES-GCM encryption of an in-memory buffer cannot fail");
+    header.extend_from_slice(&ciphertext);
+    header
+}
+
+/// append `key id length | key id | key derivation | salt` under a new salt and return the
+/// derived key
+pub(crate) fn write_key_fields(
+    out: &mut Vec<u8>,
+    key_id: &str,
+    key: &str,
+    kdf: &KeyDerivation,
+) -> [u8; KEY_LEN] {
+    let id_len = u8::try_from(key_id.len()).ex("key ids must not exceed 255 bytes");
+    out.push(id_len);
+    out.extend_from_slice(key_id.as_bytes());
+    kdf.encode(out);
+    let salt = kdf.new_salt();
+    out.extend_from_slice(&salt);
+    kdf.derive_cached(key, &salt)
+        .ex("the keyring validated the key derivation")
+}
+
+/// What [`write_key_fields`] wrote
+pub(crate) struct KeyFields<'a> {
+    pub(crate) key_id: &'a str,
+    pub(crate) kdf: KeyDerivation,
+    pub(crate) salt: &'a [u8],
+}
+
+/// read what [`write_key_fields`] wrote, returning the remaining bytes
+pub(crate) fn read_key_fields(data: &[u8]) -> Result<(KeyFields<'_>, &[u8]), DecryptError> {
+    let (&len, rest) = data.split_first().ok_or_else(truncated)?;
+    if rest.len() < usize::from(len) {
+        return Err(truncated());
+    }
+    let (id, rest) = rest.split_at(usize::from(len));
+    let key_id = std::str::from_utf8(id).map_err(|_| DecryptError {
+        details: "key id is not utf-8".to_string(),
+    })?;
+    let (kdf, rest) = KeyDerivation::decode(rest)?;
+    let (salt, rest) = split_salt(&kdf, rest)?;
+    Ok((KeyFields { key_id, kdf, salt }, rest))
+}
+
+fn split_salt<'a>(
+    kdf: &KeyDerivation,
+    data: &'a [u8],
+) -> Result<(&'a [u8], &'a [u8]), DecryptError> {
+    let salt_len = if kdf.salted() { SALT_LEN } else { 0 };
+    if data.len() < salt_len {
+        return Err(truncated());
+    }
+    Ok(data.split_at(salt_len))
+}
+
+pub(crate) fn truncated() -> DecryptError {
+    DecryptError {
+        details: "ciphertext is truncated".to_string(),
+    }
+}
+
+/// split a ciphertext into its header fields without decrypting it
+pub(crate) fn parse(data: &[u8]) -> Result<Sealed<'_>, DecryptError> {
+    if !is_sealed(data) || data.len() < MAGIC.len() + 2 {
+        return Err(truncated());
+    }
+    let (version, alg) = (data[MAGIC.len()], data[MAGIC.len() + 1]);
+    if version != VERSION {
+        return Err(DecryptError {
+            details: format!("unsupported ciphertext version {}", version),
+        });
+    }
+    if alg != ALG_AES_256_GCM {
+        return Err(DecryptError {
+            details: format!("unsupported cipher algorithm {}", alg),
+        });
+    }
+    let (fields, rest) = read_key_fields(&data[MAGIC.len() + 2..])?;
+    if rest.len() < NONCE_LEN {
+        return Err(truncated());
+    }
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    Ok(Sealed {
+        key_id: fields.key_id,
+        kdf: fields.kdf,
+        header: &data[..data.len() - ciphertext.len()],
+        salt: fields.salt,
+        nonce,
+        ciphertext,
+    })
+}
+
+impl Sealed<'_> {
+    pub(crate) fn open(&self, key: &str) -> Result<Vec<u8>, DecryptError> {
+        cipher(&self.kdf.derive_cached(key, self.salt)?)
+            .decrypt(
+                Nonce::from_slice(self.nonce),
+                Payload {
+                    msg: self.ciphertext,
+                    aad: self.header,
+                },
+            )
+            .map_err(|_| DecryptError {
+                details: "authentication failed, wrong key or tampered ciphertext".to_string(),
+            })
+    }
+}
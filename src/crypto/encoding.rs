@@ -0,0 +1,60 @@
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+
+use crate::errors::DecodeError;
+
+/// Lowercase hex
+pub fn to_hex(data: impl AsRef<[u8]>) -> String {
+    hex::encode(data)
+}
+
+/// Decode hex in either case
+pub fn from_hex(value: &str) -> Result<Vec<u8>, DecodeError> {
+    hex::decode(value.trim()).map_err(|e| DecodeError {
+        details: format!("invalid hex: {}", e),
+    })
+}
+
+/// Standard base64 with padding
+pub fn to_base64(data: impl AsRef<[u8]>) -> String {
+    STANDARD.encode(data)
+}
+
+pub fn from_base64(value: &str) -> Result<Vec<u8>, DecodeError> {
+    STANDARD.decode(value.trim()).map_err(|e| DecodeError {
+        details: format!("invalid base64: {}", e),
+    })
+}
+
+/// URL-safe base64 without padding, as used in URLs and JWTs
+pub fn to_base64_url(data: impl AsRef<[u8]>) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+/// Decode URL-safe base64, padding is accepted but not required
+pub fn from_base64_url(value: &str) -> Result<Vec<u8>, DecodeError> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim().trim_end_matches('='))
+        .map_err(|e| DecodeError {
+            details: format!("invalid base64: {}", e),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::{from_base64, from_base64_url, from_hex, to_base64, to_base64_url, to_hex};
+
+    #[test]
+    fn encodings() {
+        let data = b"\xfb\xff\x00busy";
+        assert_eq!(to_hex(data), "fbff0062757379");
+        assert_eq!(from_hex("FBFF0062757379").unwrap(), data);
+        assert!(from_hex("abc").is_err());
+
+        assert_eq!(to_base64(data), "+/8AYnVzeQ==");
+        assert_eq!(from_base64(&to_base64(data)).unwrap(), data);
+        assert_eq!(to_base64_url(data), "-_8AYnVzeQ");
+        assert_eq!(from_base64_url("-_8AYnVzeQ==").unwrap(), data);
+        assert!(from_base64_url("+/8AYnVzeQ").is_err());
+    }
+}
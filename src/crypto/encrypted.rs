@@ -0,0 +1,211 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use serde::de::{self, DeserializeOwned, IntoDeserializer};
+use serde::ser;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::config::secret::{is_encrypted, master_keyring};
+use crate::crypto::Keyring;
+use crate::errors::DecryptError;
+
+thread_local! {
+    static KEYRINGS: RefCell<Vec<Keyring>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A value kept in clear in memory and encrypted whenever it is serialized.
+///
+/// It serializes as the base64 ciphertext of its value, like [`Keyring::encrypt`], and
+/// deserializes from such a ciphertext or an `ENC(...)` marker. The keyring is the one passed
+/// to [`with_keyring`] around the (de)serialization, or the
+/// [`crate::config::secret::master_keyring`] otherwise. Strings, numbers and booleans are
+/// encrypted as their text so `ENC(...)` values written by `busylib-crypt` can be read, other
+/// values as JSON.
+///
+/// The [`with_keyring`] keyring is thread-local: values (de)serialized on another thread or
+/// in another task, even one spawned inside the closure, use the master keyring. Every field
+/// is a ciphertext of its own with the key derivation of the keyring and its own salt, so
+/// every field costs a derivation when it is encrypted and the first time it is decrypted.
+/// Prefer [`crate::crypto::KeyDerivation::Sha256`] for random keys.
+///
+/// [`crate::config::ConfigLoader`] decrypts `ENC(...)` strings before deserializing, so in
+/// config files `Encrypted` fields need the bare ciphertext, as printed by
+/// `busylib-crypt encrypt --raw`.
+///
+/// ```rust,ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct Account {
+///     name: String,
+///     api_token: Encrypted<String>,
+/// }
+///
+/// let account: Account = with_keyring(&tenant_keyring, || serde_json::from_str(&stored))?;
+/// client.bearer_auth(&*account.api_token);
+/// ```
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Encrypted<T>(T);
+
+impl<T> Encrypted<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Encrypted<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Deref for Encrypted<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Encrypted<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> fmt::Debug for Encrypted<T> {
+    /// never prints the value
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Encrypted(***)")
+    }
+}
+
+impl<T: Serialize> Serialize for Encrypted<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let plaintext = match serde_json::to_value(&self.0).map_err(ser::Error::custom)? {
+            Value::String(s) => s,
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            value => value.to_string(),
+        };
+        let keyring = keyring().map_err(ser::Error::custom)?;
+        serializer.serialize_str(&keyring.encrypt(&plaintext))
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Encrypted<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let value = value.trim();
+        let ciphertext = if is_encrypted(value) {
+            &value[4..value.len() - 1]
+        } else {
+            value
+        };
+        let plaintext = keyring()
+            .and_then(|keyring| keyring.decrypt(ciphertext))
+            .map_err(de::Error::custom)?;
+        // text first, so that a string holding JSON stays a string
+        let text: Result<T, de::value::Error> =
+            T::deserialize(plaintext.as_str().into_deserializer());
+        match text {
+            Ok(value) => Ok(Self(value)),
+            Err(_) => serde_json::from_str(&plaintext)
+                .map(Self)
+                .map_err(|e| de::Error::custom(format!("decrypted value is invalid: {}", e))),
+        }
+    }
+}
+
+/// Run `f` with `keyring` encrypting and decrypting the [`Encrypted`] values it (de)serializes
+/// on this thread, instead of the master keyring. Threads and tasks spawned by `f` do not
+/// inherit it.
+pub fn with_keyring<R>(keyring: &Keyring, f: impl FnOnce() -> R) -> R {
+    struct Pop;
+
+    impl Drop for Pop {
+        fn drop(&mut self) {
+            let _ = KEYRINGS.try_with(|keyrings| keyrings.borrow_mut().pop());
+        }
+    }
+
+    KEYRINGS.with(|keyrings| keyrings.borrow_mut().push(keyring.clone()));
+    let _pop = Pop;
+    f()
+}
+
+/// the innermost [`with_keyring`] keyring, or the master keyring
+fn keyring() -> Result<Keyring, DecryptError> {
+    if let Some(keyring) = KEYRINGS.with(|keyrings| keyrings.borrow().last().cloned()) {
+        return Ok(keyring);
+    }
+    master_keyring()
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{Deserialize, Serialize};
+
+    use crate::config::secret::{encrypt_value, MASTER_KEY_VAR};
+    use crate::config::EnvOverrides;
+    use crate::crypto::{with_keyring, Encrypted, KeyDerivation, Keyring};
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Tls {
+        cert: String,
+        verify: bool,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Database {
+        host: String,
+        password: Encrypted<String>,
+        port: Encrypted<u16>,
+        tls: Encrypted<Tls>,
+    }
+
+    #[test]
+    fn encrypted_fields() {
+        let keyring = Keyring::new("tenant", "foo")
+            .key_derivation(KeyDerivation::Pbkdf2 { iterations: 1000 })
+            .unwrap();
+        let database = Database {
+            host: "db".to_string(),
+            password: Encrypted::new(r#"{"not": "json"}"#.to_string()),
+            port: 5432.into(),
+            tls: Encrypted::new(Tls {
+                cert: "PEM".to_string(),
+                verify: true,
+            }),
+        };
+        let json = with_keyring(&keyring, || serde_json::to_string(&database)).unwrap();
+        assert!(json.contains(r#""host":"db""#));
+        assert!(!json.contains("5432") && !json.contains("PEM") && !json.contains("json"));
+        let parsed: Database = with_keyring(&keyring, || serde_json::from_str(&json)).unwrap();
+        assert_eq!(parsed, database);
+        assert_eq!(*parsed.port + 1, 5433);
+        assert_eq!(format!("{:?}", parsed.password), "Encrypted(***)");
+
+        let other = Keyring::new("tenant", "bar");
+        let err = with_keyring(&other, || serde_json::from_str::<Database>(&json)).unwrap_err();
+        assert!(err.to_string().contains("authentication failed"));
+
+        // the master keyring outside a context, reading ENC(...) values from busylib-crypt
+        let _guard = EnvOverrides::new().set(MASTER_KEY_VAR, "master").enter();
+        let toml = format!(
+            "host = \"db\"\npassword = \"{}\"\nport = \"{}\"\ntls = \"{}\"\n",
+            encrypt_value("s3cret", "master"),
+            encrypt_value("5432", "master"),
+            encrypt_value(r#"{"cert": "PEM", "verify": false}"#, "master"),
+        );
+        let parsed: Database = toml::from_str(&toml).unwrap();
+        assert_eq!(*parsed.password, "s3cret");
+        assert_eq!(*parsed.port, 5432);
+        assert!(!parsed.tls.verify);
+        assert!(with_keyring(&keyring, || toml::from_str::<Database>(&toml)).is_err());
+    }
+}
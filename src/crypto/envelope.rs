@@ -0,0 +1,320 @@
+//! Envelope encryption: every value is encrypted under its own random data key, and only that
+//! data key is encrypted ("wrapped") by a [`KeyProvider`] holding the master key.
+//!
+//! Layout: `MAGIC | version | algorithm | key id | wrapped key length | wrapped key | nonce |
+//! ciphertext`. The key id names the master key for the provider and is prefixed with its
+//! length, the wrapped key length is two bytes big-endian. Everything before the ciphertext is
+//! authenticated as associated data.
+
+use std::fs::{self, OpenOptions};
+use std::future::{self, Future};
+use std::io::Write;
+use std::path::Path;
+use std::pin::Pin;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, OsRng, Payload};
+use aes_gcm::{AeadCore, Aes256Gcm, Nonce};
+
+use crate::crypto::aead::{cipher, truncated, MAGIC, NONCE_LEN, VERSION};
+use crate::crypto::{from_base64, to_base64};
+use crate::errors::{DecryptError, KeyProviderError};
+use crate::prelude::EnhancedExpect;
+
+const ALG_ENVELOPE: u8 = 3;
+const DATA_KEY_LEN: usize = 32;
+
+/// What [`KeyProvider::wrap`] and [`KeyProvider::unwrap`] return, boxed so that providers
+/// can be used as `&dyn KeyProvider`
+pub type KeyFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<u8>, KeyProviderError>> + Send + 'a>>;
+
+/// Wraps and unwraps data keys with a master key that never leaves the provider, such as a
+/// local key file or a cloud KMS. Wrapping and unwrapping are asynchronous so a provider can
+/// call a remote service without blocking the runtime.
+///
+/// ```rust,ignore
+/// impl KeyProvider for Kms {
+///     fn key_id(&self) -> String {
+///         self.key_arn.clone()
+///     }
+///
+///     fn wrap<'a>(&'a self, data_key: &'a [u8]) -> KeyFuture<'a> {
+///         Box::pin(async move { self.client.encrypt(&self.key_arn, data_key).await })
+///     }
+///
+///     fn unwrap<'a>(&'a self, key_id: &'a str, wrapped: &'a [u8]) -> KeyFuture<'a> {
+///         Box::pin(async move { self.client.decrypt(key_id, wrapped).await })
+///     }
+/// }
+/// ```
+pub trait KeyProvider: Send + Sync {
+    /// Id of the master key new data keys are wrapped with, stored in the envelope. Must not
+    /// exceed 255 bytes.
+    fn key_id(&self) -> String;
+
+    /// Encrypt `data_key` with the master key [`KeyProvider::key_id`]
+    fn wrap<'a>(&'a self, data_key: &'a [u8]) -> KeyFuture<'a>;
+
+    /// Decrypt a data key wrapped with the master key `key_id`
+    fn unwrap<'a>(&'a self, key_id: &'a str, wrapped: &'a [u8]) -> KeyFuture<'a>;
+}
+
+/// Encrypt `plaintext` under a fresh data key wrapped by `provider`
+pub async fn encrypt_envelope(
+    provider: &dyn KeyProvider,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, KeyProviderError> {
+    let mut data_key = [0u8; DATA_KEY_LEN];
+    OsRng.fill_bytes(&mut data_key);
+    let key_id = provider.key_id();
+    let wrapped = provider.wrap(&data_key).await?;
+    let id_len = u8::try_from(key_id.len())
+        .map_err(|_| KeyProviderError::new(format!("key id `{}` exceeds 255 bytes", key_id)))?;
+    let wrapped_len = u16::try_from(wrapped.len())
+        .map_err(|_| KeyProviderError::new("wrapped data key exceeds 65535 bytes"))?;
+
+    let mut header = Vec::with_capacity(64 + key_id.len() + wrapped.len() + plaintext.len());
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&[VERSION, ALG_ENVELOPE, id_len]);
+    header.extend_from_slice(key_id.as_bytes());
+    header.extend_from_slice(&wrapped_len.to_be_bytes());
+    header.extend_from_slice(&wrapped);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    header.extend_from_slice(&nonce);
+    let ciphertext = cipher(&data_key)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: &header,
+            },
+        )
+        .ex("AES-GCM encryption of an in-memory buffer cannot fail");
+    header.extend_from_slice(&ciphertext);
+    Ok(header)
+}
+
+/// Decrypt what [`encrypt_envelope`] wrote, unwrapping the data key with `provider`
+pub async fn decrypt_envelope(
+    provider: &dyn KeyProvider,
+    data: &[u8],
+) -> Result<Vec<u8>, DecryptError> {
+    let fixed = MAGIC.len() + 3;
+    if data.len() < fixed || &data[..MAGIC.len()] != MAGIC {
+        return Err(DecryptError {
+            details: "not an envelope".to_string(),
+        });
+    }
+    let (version, alg) = (data[MAGIC.len()], data[MAGIC.len() + 1]);
+    if version != VERSION || alg != ALG_ENVELOPE {
+        return Err(DecryptError {
+            details: format!(
+                "unsupported envelope version {} or algorithm {}",
+                version, alg
+            ),
+        });
+    }
+    let rest = &data[fixed..];
+    let id_len = usize::from(data[fixed - 1]);
+    if rest.len() < id_len + 2 {
+        return Err(truncated());
+    }
+    let (key_id, rest) = rest.split_at(id_len);
+    let key_id = std::str::from_utf8(key_id).map_err(|_| DecryptError {
+        details: "key id is not utf-8".to_string(),
+    })?;
+    let wrapped_len = usize::from(u16::from_be_bytes([rest[0], rest[1]]));
+    let rest = &rest[2..];
+    if rest.len() < wrapped_len + NONCE_LEN {
+        return Err(truncated());
+    }
+    let (wrapped, rest) = rest.split_at(wrapped_len);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let data_key = provider.unwrap(key_id, wrapped).await?;
+    if data_key.len() != DATA_KEY_LEN {
+        return Err(DecryptError {
+            details: format!("unwrapped data key has {} bytes", data_key.len()),
+        });
+    }
+    cipher(&data_key)
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &data[..data.len() - ciphertext.len()],
+            },
+        )
+        .map_err(|_| DecryptError {
+            details: "authentication failed, tampered envelope".to_string(),
+        })
+}
+
+/// A [`KeyProvider`] holding a 256-bit master key read from a local file, for offline use and
+/// tests. The file holds the key in base64 and its name is the key id, so one file per tenant
+/// gives per-tenant keys.
+///
+/// ```rust,ignore
+/// let provider = LocalKeyProvider::generate("/etc/app/keys/tenant-42.key")?;
+/// let sealed = encrypt_envelope(&provider, &blob).await?;
+/// let provider = LocalKeyProvider::from_file("/etc/app/keys/tenant-42.key")?;
+/// let blob = decrypt_envelope(&provider, &sealed).await?;
+/// ```
+#[derive(Clone)]
+pub struct LocalKeyProvider {
+    id: String,
+    key: [u8; DATA_KEY_LEN],
+}
+
+impl LocalKeyProvider {
+    pub fn new(id: &str, key: [u8; DATA_KEY_LEN]) -> Self {
+        assert!(id.len() <= 255, "key id `{}` exceeds 255 bytes", id);
+        Self {
+            id: id.to_string(),
+            key,
+        }
+    }
+
+    /// Read a master key file, the key id is the file name without extension
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, KeyProviderError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|e| {
+            KeyProviderError::new(format!("cannot read key file {}: {}", path.display(), e))
+        })?;
+        let key = from_base64(&content)
+            .ok()
+            .and_then(|key| <[u8; DATA_KEY_LEN]>::try_from(key).ok())
+            .ok_or_else(|| {
+                KeyProviderError::new(format!(
+                    "key file {} does not hold a base64 encoded 32-byte key",
+                    path.display()
+                ))
+            })?;
+        Ok(Self::new(&id_of(path), key))
+    }
+
+    /// Write a new random master key to `path`, readable by the owner only, and fail if the
+    /// file exists
+    pub fn generate(path: impl AsRef<Path>) -> Result<Self, KeyProviderError> {
+        let path = path.as_ref();
+        let mut key = [0u8; DATA_KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", to_base64(key)))
+            .map_err(|e| {
+                KeyProviderError::new(format!("cannot write key file {}: {}", path.display(), e))
+            })?;
+        Ok(Self::new(&id_of(path), key))
+    }
+
+    fn wrap_key(&self, data_key: &[u8]) -> Result<Vec<u8>, KeyProviderError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let wrapped = cipher(&self.key)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: data_key,
+                    aad: self.id.as_bytes(),
+                },
+            )
+            .map_err(|_| KeyProviderError::new("failed to wrap the data key"))?;
+        Ok([nonce.as_slice(), &wrapped].concat())
+    }
+
+    fn unwrap_key(&self, key_id: &str, wrapped: &[u8]) -> Result<Vec<u8>, KeyProviderError> {
+        if key_id != self.id {
+            return Err(KeyProviderError::new(format!(
+                "unknown key id `{}`, this provider holds `{}`",
+                key_id, self.id
+            )));
+        }
+        if wrapped.len() < NONCE_LEN {
+            return Err(KeyProviderError::new("wrapped data key is truncated"));
+        }
+        let (nonce, wrapped) = wrapped.split_at(NONCE_LEN);
+        cipher(&self.key)
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: wrapped,
+                    aad: self.id.as_bytes(),
+                },
+            )
+            .map_err(|_| KeyProviderError::new("wrong master key or tampered data key"))
+    }
+}
+
+impl KeyProvider for LocalKeyProvider {
+    fn key_id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn wrap<'a>(&'a self, data_key: &'a [u8]) -> KeyFuture<'a> {
+        Box::pin(future::ready(self.wrap_key(data_key)))
+    }
+
+    fn unwrap<'a>(&'a self, key_id: &'a str, wrapped: &'a [u8]) -> KeyFuture<'a> {
+        Box::pin(future::ready(self.unwrap_key(key_id, wrapped)))
+    }
+}
+
+impl std::fmt::Debug for LocalKeyProvider {
+    /// never prints the key
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("LocalKeyProvider")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+fn id_of(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use crate::crypto::{decrypt_envelope, encrypt_envelope, KeyProvider, LocalKeyProvider};
+
+    #[tokio::test]
+    async fn envelope_roundtrip() {
+        let dir = env::temp_dir().join("busylib_envelope_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tenant-42.key");
+        let _ = std::fs::remove_file(&path);
+        let provider = LocalKeyProvider::generate(&path).unwrap();
+        assert_eq!(provider.key_id(), "tenant-42");
+        assert!(LocalKeyProvider::generate(&path).is_err());
+
+        let blob = vec![42u8; 100_000];
+        let sealed = encrypt_envelope(&provider, &blob).await.unwrap();
+        assert_ne!(sealed, encrypt_envelope(&provider, &blob).await.unwrap());
+        let reloaded = LocalKeyProvider::from_file(&path).unwrap();
+        assert_eq!(decrypt_envelope(&reloaded, &sealed).await.unwrap(), blob);
+
+        let other = LocalKeyProvider::new("tenant-42", [7; 32]);
+        assert!(decrypt_envelope(&other, &sealed).await.is_err());
+        let other = LocalKeyProvider::new("tenant-43", [7; 32]);
+        let err = decrypt_envelope(&other, &sealed).await.unwrap_err();
+        assert!(err.to_string().contains("tenant-42"));
+        for i in [6, 20, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1;
+            assert!(decrypt_envelope(&provider, &tampered).await.is_err());
+        }
+        assert!(decrypt_envelope(&provider, &sealed[..40]).await.is_err());
+        assert_eq!(
+            format!("{:?}", provider),
+            "LocalKeyProvider { id: \"tenant-42\" }"
+        );
+    }
+}
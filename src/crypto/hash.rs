@@ -0,0 +1,199 @@
+use std::io;
+use std::path::Path;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+use subtle::ConstantTimeEq;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Bytes read at once when hashing files and streams
+const BUFFER_SIZE: usize = 64 * 1024;
+/// BLAKE3 key derivation context turning a MAC key of any length into a 32-byte key
+const BLAKE3_MAC_CONTEXT: &str = "busylib HashAlgorithm::Blake3 hmac key";
+
+/// A digest algorithm, also used for HMAC.
+///
+/// ```rust,ignore
+/// let checksum = to_hex(HashAlgorithm::Sha256.digest_file("release.tar.gz").await?);
+/// let valid = HashAlgorithm::Sha256.verify_hmac(secret, &body, &from_hex(signature)?);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+    /// MACs use the keyed mode of BLAKE3 under a key derived from the given one, not HMAC
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// Length of a digest in bytes
+    pub fn output_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => 32,
+            HashAlgorithm::Sha512 => 64,
+        }
+    }
+
+    /// A hasher to feed incrementally
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    /// Digest of bytes or a string
+    pub fn digest(&self, data: impl AsRef<[u8]>) -> Vec<u8> {
+        let mut hasher = self.hasher();
+        hasher.update(data.as_ref());
+        hasher.finalize()
+    }
+
+    /// Digest of everything `reader` yields
+    pub async fn digest_reader<R: AsyncRead + Unpin>(&self, reader: &mut R) -> io::Result<Vec<u8>> {
+        let mut hasher = self.hasher();
+        let mut buffer = vec![0u8; BUFFER_SIZE];
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                return Ok(hasher.finalize());
+            }
+            hasher.update(&buffer[..read]);
+        }
+    }
+
+    /// Digest of the file at `path`, read in chunks
+    pub async fn digest_file(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        self.digest_reader(&mut File::open(path).await?).await
+    }
+
+    /// HMAC of `message` under `key`, the keyed BLAKE3 hash for [`HashAlgorithm::Blake3`]
+    pub fn hmac(&self, key: &[u8], message: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha256 => mac::<Hmac<Sha256>>(key, message),
+            HashAlgorithm::Sha512 => mac::<Hmac<Sha512>>(key, message),
+            HashAlgorithm::Blake3 => {
+                let key = blake3::derive_key(BLAKE3_MAC_CONTEXT, key);
+                blake3::keyed_hash(&key, message).as_bytes().to_vec()
+            }
+        }
+    }
+
+    /// Whether `signature` is the HMAC of `message` under `key`, compared in constant time
+    pub fn verify_hmac(&self, key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        constant_time_eq(&self.hmac(key, message), signature)
+    }
+}
+
+/// Incremental digest, see [`HashAlgorithm::hasher`]. Implements [`io::Write`] so that
+/// blocking readers can be hashed with [`io::copy`].
+#[derive(Clone)]
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => Digest::update(hasher, data),
+            Hasher::Sha512(hasher) => Digest::update(hasher, data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+impl io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compare two byte strings in time independent of where they differ, for secrets such as
+/// signatures and tokens. Only the length may leak.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+fn mac<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = <M as hmac::digest::KeyInit>::new_from_slice(key)
+        .unwrap_or_else(|_| unreachable!("HMAC accepts keys of any length"));
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::io;
+
+    use crate::crypto::{constant_time_eq, from_hex, to_hex, HashAlgorithm};
+
+    #[tokio::test]
+    async fn digests_and_hmac() {
+        let cases = [
+            (
+                HashAlgorithm::Sha256,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                HashAlgorithm::Sha512,
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            ),
+            (
+                HashAlgorithm::Blake3,
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            ),
+        ];
+        let path = env::temp_dir().join("busylib_hash_test");
+        std::fs::write(&path, "abc").unwrap();
+        for (alg, expected) in cases {
+            assert_eq!(to_hex(alg.digest("abc")), expected);
+            assert_eq!(alg.digest(b"abc").len(), alg.output_len());
+            assert_eq!(to_hex(alg.digest_file(&path).await.unwrap()), expected);
+            let mut hasher = alg.hasher();
+            io::copy(&mut &b"abc"[..], &mut hasher).unwrap();
+            assert_eq!(to_hex(hasher.finalize()), expected);
+        }
+
+        // RFC 4231 test case 2
+        let signature =
+            from_hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843").unwrap();
+        let message = b"what do ya want for nothing?";
+        assert_eq!(HashAlgorithm::Sha256.hmac(b"Jefe", message), signature);
+        assert!(HashAlgorithm::Sha256.verify_hmac(b"Jefe", message, &signature));
+        assert!(!HashAlgorithm::Sha256.verify_hmac(b"Jeff", message, &signature));
+        assert!(!HashAlgorithm::Sha256.verify_hmac(b"Jefe", message, &signature[..31]));
+
+        // computed with an independent implementation of BLAKE3 derive_key and keyed_hash
+        let message = b"The quick brown fox jumps over the lazy dog";
+        let signature =
+            from_hex("edaaebfb185046f80679909b8c2811210a495de6857a0d45a403bbe94d7823ae").unwrap();
+        assert_eq!(HashAlgorithm::Blake3.hmac(b"key", message), signature);
+        assert!(HashAlgorithm::Blake3.verify_hmac(b"key", message, &signature));
+        assert!(!HashAlgorithm::Blake3.verify_hmac(b"kez", message, &signature));
+
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"toke"));
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use argon2::{Algorithm, Argon2, Params, Version};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+
+use crate::errors::DecryptError;
+
+pub(crate) const KEY_LEN: usize = 32;
+pub(crate) const SALT_LEN: usize = 16;
+
+/// upper bounds of the parameters, checked before deriving and when reading a ciphertext
+/// header, so a forged header cannot make decryption allocate more than 256 MiB or spin for
+/// more than a few seconds
+const MAX_ARGON2_MEMORY_KIB: u32 = 256 * 1024;
+const MAX_ARGON2_ITERATIONS: u32 = 10;
+const MAX_ARGON2_PARALLELISM: u32 = 16;
+const MAX_PBKDF2_ITERATIONS: u32 = 2_000_000;
+
+/// number of derived keys kept by [`KeyDerivation::derive_cached`]
+const CACHE_CAPACITY: usize = 1024;
+
+/// passphrases are only kept as a keyed hash under this random key
+static PROCESS_KEY: Lazy<[u8; 32]> = Lazy::new(|| {
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    key
+});
+/// derived keys by parameters, passphrase and salt
+static DERIVED: Lazy<Mutex<DerivedKeys>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// parameters and keyed hash of a passphrase
+type Passphrase = (KeyDerivation, [u8; 32]);
+type Salt = [u8; SALT_LEN];
+type DerivedKeys = HashMap<(Passphrase, Salt), [u8; KEY_LEN]>;
+
+const ID_SHA256: u8 = 0;
+const ID_ARGON2ID: u8 = 1;
+const ID_PBKDF2: u8 = 2;
+
+/// How an encryption key is derived from a passphrase. The parameters and a random salt are
+/// stored in the ciphertext header, so decryption only needs the passphrase. Argon2id is
+/// limited to 256 MiB, 10 iterations and 16 lanes, PBKDF2 to 2 000 000 iterations.
+///
+/// Every ciphertext gets a salt of its own, so every encryption pays for a derivation. Derived
+/// keys are cached by parameters, passphrase and salt, so only the first decryption of a
+/// ciphertext does.
+///
+/// ```rust,ignore
+/// let strong = KeyDerivation::Argon2id { memory_kib: 64 * 1024, iterations: 3, parallelism: 1 };
+/// let encrypted = encrypt_by_key_with(value, "passphrase", &strong)?;
+/// let decrypted = decrypt_by_key_with_error(encrypted, "passphrase")?;
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyDerivation {
+    /// a single unsalted SHA-256, only for keys that are already random
+    Sha256,
+    /// Argon2id (RFC 9106), memory hard
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+    /// PBKDF2-HMAC-SHA256, for environments that require FIPS approved algorithms
+    Pbkdf2 { iterations: u32 },
+}
+
+impl Default for KeyDerivation {
+    /// [`KeyDerivation::argon2id`]
+    fn default() -> Self {
+        Self::argon2id()
+    }
+}
+
+impl KeyDerivation {
+    /// Argon2id with 19 MiB, 2 iterations and 1 lane, the OWASP recommendation
+    pub fn argon2id() -> Self {
+        KeyDerivation::Argon2id {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+
+    /// PBKDF2-HMAC-SHA256 with 600 000 iterations, the OWASP recommendation
+    pub fn pbkdf2() -> Self {
+        KeyDerivation::Pbkdf2 {
+            iterations: 600_000,
+        }
+    }
+
+    /// Derive a 256-bit key from `passphrase` and `salt`, failing for parameters
+    /// [`KeyDerivation::validate`] rejects
+    pub fn derive(&self, passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LEN], DecryptError> {
+        self.validate()?;
+        let mut key = [0u8; KEY_LEN];
+        match *self {
+            KeyDerivation::Sha256 => key.copy_from_slice(&Sha256::digest(passphrase.as_bytes())),
+            KeyDerivation::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params = Params::new(memory_kib, iterations, parallelism, Some(KEY_LEN))
+                    .map_err(|e| DecryptError {
+                        details: format!("invalid argon2 parameters: {}", e),
+                    })?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                    .map_err(|e| DecryptError {
+                        details: format!("argon2 key derivation failed: {}", e),
+                    })?;
+            }
+            KeyDerivation::Pbkdf2 { iterations } => {
+                pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+            }
+        }
+        Ok(key)
+    }
+
+    /// Like [`KeyDerivation::derive`], reusing the key derived before for the same parameters,
+    /// passphrase and salt, so a value is only expensive to decrypt the first time
+    pub(crate) fn derive_cached(
+        &self,
+        passphrase: &str,
+        salt: &[u8],
+    ) -> Result<[u8; KEY_LEN], DecryptError> {
+        let Ok(salt) = Salt::try_from(salt) else {
+            return self.derive(passphrase, salt);
+        };
+        let cache_key = ((*self, fingerprint(passphrase)), salt);
+        if let Some(key) = DERIVED.lock().ok().and_then(|d| d.get(&cache_key).copied()) {
+            return Ok(key);
+        }
+        let key = self.derive(passphrase, &salt)?;
+        if let Ok(mut derived) = DERIVED.lock() {
+            if derived.len() >= CACHE_CAPACITY {
+                derived.clear();
+            }
+            derived.insert(cache_key, key);
+        }
+        Ok(key)
+    }
+
+    /// A random salt for a new ciphertext, empty when the derivation takes no salt
+    pub(crate) fn new_salt(&self) -> Vec<u8> {
+        let mut salt = vec![0; if self.salted() { SALT_LEN } else { 0 }];
+        OsRng.fill_bytes(&mut salt);
+        salt
+    }
+
+    /// whether the derivation takes a salt
+    pub(crate) fn salted(&self) -> bool {
+        !matches!(self, KeyDerivation::Sha256)
+    }
+
+    /// id and big-endian parameters as written in a ciphertext header
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            KeyDerivation::Sha256 => out.push(ID_SHA256),
+            KeyDerivation::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                out.push(ID_ARGON2ID);
+                out.extend_from_slice(&memory_kib.to_be_bytes());
+                out.extend_from_slice(&iterations.to_be_bytes());
+                out.extend_from_slice(&parallelism.to_be_bytes());
+            }
+            KeyDerivation::Pbkdf2 { iterations } => {
+                out.push(ID_PBKDF2);
+                out.extend_from_slice(&iterations.to_be_bytes());
+            }
+        }
+    }
+
+    /// read what [`KeyDerivation::encode`] wrote, returning the remaining bytes
+    pub(crate) fn decode(data: &[u8]) -> Result<(Self, &[u8]), DecryptError> {
+        let truncated = || DecryptError {
+            details: "ciphertext header is truncated".to_string(),
+        };
+        let (&id, rest) = data.split_first().ok_or_else(truncated)?;
+        let mut fields = rest
+            .chunks_exact(4)
+            .map(|c| u32::from_be_bytes(c.try_into().unwrap_or_default()));
+        let mut next = || fields.next().ok_or_else(truncated);
+        let (kdf, used) = match id {
+            ID_SHA256 => (KeyDerivation::Sha256, 0),
+            ID_ARGON2ID => {
+                let kdf = KeyDerivation::Argon2id {
+                    memory_kib: next()?,
+                    iterations: next()?,
+                    parallelism: next()?,
+                };
+                (kdf, 12)
+            }
+            ID_PBKDF2 => (
+                KeyDerivation::Pbkdf2 {
+                    iterations: next()?,
+                },
+                4,
+            ),
+            id => {
+                return Err(DecryptError {
+                    details: format!("unsupported key derivation {}", id),
+                })
+            }
+        };
+        kdf.validate()?;
+        Ok((kdf, &rest[used..]))
+    }
+
+    /// Fail for parameters Argon2id or PBKDF2 cannot use and for parameters beyond the limits
+    pub fn validate(&self) -> Result<(), DecryptError> {
+        let within = match *self {
+            KeyDerivation::Sha256 => true,
+            KeyDerivation::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                Params::new(memory_kib, iterations, parallelism, Some(KEY_LEN)).map_err(|e| {
+                    DecryptError {
+                        details: format!("invalid argon2 parameters: {}", e),
+                    }
+                })?;
+                memory_kib <= MAX_ARGON2_MEMORY_KIB
+                    && iterations <= MAX_ARGON2_ITERATIONS
+                    && parallelism <= MAX_ARGON2_PARALLELISM
+            }
+            KeyDerivation::Pbkdf2 { iterations: 0 } => {
+                return Err(DecryptError {
+                    details: "pbkdf2 needs at least one iteration".to_string(),
+                })
+            }
+            KeyDerivation::Pbkdf2 { iterations } => iterations <= MAX_PBKDF2_ITERATIONS,
+        };
+        if within {
+            Ok(())
+        } else {
+            Err(DecryptError {
+                details: format!("key derivation parameters {:?} exceed the limits", self),
+            })
+        }
+    }
+}
+
+/// a keyed hash of `passphrase`, so the caches do not hold it
+fn fingerprint(passphrase: &str) -> [u8; 32] {
+    *blake3::keyed_hash(&PROCESS_KEY, passphrase.as_bytes()).as_bytes()
+}
@@ -0,0 +1,243 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::crypto::aead;
+use crate::crypto::{decrypt_legacy, is_legacy, KeyDerivation};
+use crate::errors::DecryptError;
+use crate::prelude::EnhancedExpect;
+
+/// Several named keys, one of them used to encrypt.
+///
+/// Ciphertexts carry the id of their key, so keys can be rotated without re-encrypting
+/// everything at once: add the new key as primary, keep the old ones for decryption and
+/// [`Keyring::reencrypt`] old values when convenient. Only legacy magic-crypt values have no
+/// key id, they are tried with every key, primary first.
+///
+/// ```rust,ignore
+/// let keyring = Keyring::new("2024", &old_key).rotate("2025", &new_key);
+/// let encrypted = keyring.encrypt("secret");
+/// if keyring.needs_reencrypt(&stored) {
+///     stored = keyring.reencrypt(&stored)?;
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Keyring {
+    /// in insertion order, the primary key is tried first anyway
+    keys: Vec<(String, String)>,
+    primary: String,
+    kdf: KeyDerivation,
+}
+
+impl Keyring {
+    /// A keyring whose only key `key` is primary. Key ids must not exceed 255 bytes.
+    pub fn new(id: &str, key: &str) -> Self {
+        Self {
+            keys: Vec::new(),
+            primary: id.to_string(),
+            kdf: KeyDerivation::default(),
+        }
+        .add(id, key)
+    }
+
+    /// Add a key used for decryption only, replacing any key with the same id
+    pub fn add(mut self, id: &str, key: &str) -> Self {
+        assert!(id.len() <= 255, "key id `{}` exceeds 255 bytes", id);
+        self.keys.retain(|(known, _)| known != id);
+        self.keys.push((id.to_string(), key.to_string()));
+        self
+    }
+
+    /// Encrypt with the key `id` from now on, panics if it was not added
+    pub fn primary(mut self, id: &str) -> Self {
+        assert!(self.key(id).is_some(), "key `{}` is not in the keyring", id);
+        self.primary = id.to_string();
+        self
+    }
+
+    /// Add `key` and make it primary, previous keys stay available for decryption
+    pub fn rotate(self, id: &str, key: &str) -> Self {
+        self.add(id, key).primary(id)
+    }
+
+    /// How keys are derived when encrypting, [`KeyDerivation::default`] by default. Fails for
+    /// parameters [`KeyDerivation::validate`] rejects, which could not be decrypted.
+    pub fn key_derivation(mut self, kdf: KeyDerivation) -> Result<Self, DecryptError> {
+        kdf.validate()?;
+        self.kdf = kdf;
+        Ok(self)
+    }
+
+    pub fn primary_id(&self) -> &str {
+        &self.primary
+    }
+
+    pub fn ids(&self) -> Vec<&str> {
+        self.keys.iter().map(|(id, _)| id.as_str()).collect()
+    }
+
+    /// Encrypt with the primary key, the result is base64
+    pub fn encrypt(&self, value: &str) -> String {
+        STANDARD.encode(self.encrypt_bytes(value.as_bytes()))
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<String, DecryptError> {
+        if is_legacy(value) {
+            return self.decrypt_legacy(value);
+        }
+        String::from_utf8(self.decrypt_bytes(&decode(value)?)?).map_err(|e| DecryptError {
+            details: format!("decrypted value is not utf-8: {}", e),
+        })
+    }
+
+    /// Encrypt raw bytes with the primary key, see [`crate::crypto::encrypt_stream`] for data
+    /// that does not fit in memory
+    pub fn encrypt_bytes(&self, plaintext: &[u8]) -> Vec<u8> {
+        let key = self
+            .key(&self.primary)
+            .ex("the primary key is in the keyring");
+        aead::seal(plaintext, &self.primary, key, &self.kdf)
+    }
+
+    pub fn decrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>, DecryptError> {
+        let sealed = aead::parse(data)?;
+        let key = self.key(sealed.key_id).ok_or_else(|| DecryptError {
+            details: format!("unknown key id `{}`", sealed.key_id),
+        })?;
+        sealed.open(key)
+    }
+
+    /// The id of the key `value` was encrypted with, `None` if it is not a current ciphertext
+    pub fn key_id(value: &str) -> Option<String> {
+        let data = decode(value).ok()?;
+        let sealed = aead::parse(&data).ok()?;
+        Some(sealed.key_id.to_string())
+    }
+
+    /// Whether `value` is not encrypted with the primary key, the current format or the
+    /// configured key derivation
+    pub fn needs_reencrypt(&self, value: &str) -> bool {
+        if is_legacy(value) {
+            return true;
+        }
+        let Ok(data) = decode(value) else {
+            return true;
+        };
+        match aead::parse(&data) {
+            Ok(sealed) => sealed.key_id != self.primary || sealed.kdf != self.kdf,
+            Err(_) => true,
+        }
+    }
+
+    /// Decrypt `value` with whichever key it needs and encrypt it with the primary key
+    pub fn reencrypt(&self, value: &str) -> Result<String, DecryptError> {
+        self.decrypt(value)
+            .map(|plaintext| self.encrypt(&plaintext))
+    }
+
+    pub(crate) fn key(&self, id: &str) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|(known, _)| known == id)
+            .map(|(_, key)| key.as_str())
+    }
+
+    pub(crate) fn kdf(&self) -> &KeyDerivation {
+        &self.kdf
+    }
+
+    /// every key, primary first
+    fn candidates(&self) -> impl Iterator<Item = &str> {
+        self.key(&self.primary).into_iter().chain(
+            self.keys
+                .iter()
+                .filter(|(id, _)| *id != self.primary)
+                .map(|(_, key)| key.as_str()),
+        )
+    }
+
+    fn decrypt_legacy(&self, value: &str) -> Result<String, DecryptError> {
+        let mut error = None;
+        for key in self.candidates() {
+            match decrypt_legacy(value.to_string(), key) {
+                Ok(plaintext) => return Ok(plaintext),
+                Err(e) => error = Some(e),
+            }
+        }
+        Err(error.ex("a keyring holds at least one key"))
+    }
+}
+
+impl std::fmt::Debug for Keyring {
+    /// never prints the keys
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Keyring")
+            .field("ids", &self.ids())
+            .field("primary", &self.primary)
+            .field("kdf", &self.kdf)
+            .finish()
+    }
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, DecryptError> {
+    STANDARD.decode(value.trim()).map_err(|e| DecryptError {
+        details: format!("invalid base64: {}", e),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use magic_crypt::{new_magic_crypt, MagicCryptTrait};
+
+    use crate::crypto::{encrypt_by_key, KeyDerivation, Keyring};
+
+    #[test]
+    fn rotate_keys() {
+        let fast = KeyDerivation::Pbkdf2 { iterations: 1000 };
+        let old = Keyring::new("2024", "old key")
+            .key_derivation(fast)
+            .unwrap();
+        let stored = old.encrypt("secret");
+        assert_eq!(Keyring::key_id(&stored).as_deref(), Some("2024"));
+
+        let keyring = old.rotate("2025", "new key");
+        assert_eq!(keyring.primary_id(), "2025");
+        assert_eq!(keyring.decrypt(&stored).unwrap(), "secret");
+        assert!(keyring.needs_reencrypt(&stored));
+        let upgraded = keyring.reencrypt(&stored).unwrap();
+        assert_eq!(Keyring::key_id(&upgraded).as_deref(), Some("2025"));
+        assert!(!keyring.needs_reencrypt(&upgraded));
+        assert_eq!(keyring.decrypt(&upgraded).unwrap(), "secret");
+
+        let other = Keyring::new("2025", "another key");
+        assert!(other
+            .decrypt(&stored)
+            .unwrap_err()
+            .to_string()
+            .contains("2024"));
+        assert!(other.decrypt(&upgraded).is_err());
+
+        // legacy values have no key id and are tried with every key
+        let legacy = new_magic_crypt!("old key", 256).encrypt_str_to_base64("secret");
+        assert_eq!(keyring.decrypt(&legacy).unwrap(), "secret");
+        assert!(keyring.needs_reencrypt(&legacy));
+        let single = encrypt_by_key("secret".to_string(), "old key");
+        assert_eq!(Keyring::key_id(&single).as_deref(), Some(""));
+        assert!(keyring.decrypt(&single).is_err());
+        assert_eq!(
+            keyring.clone().add("", "old key").decrypt(&single).unwrap(),
+            "secret"
+        );
+        assert!(!format!("{:?}", keyring).contains("old key"));
+
+        let costly = KeyDerivation::Pbkdf2 {
+            iterations: u32::MAX,
+        };
+        assert!(keyring.clone().key_derivation(costly).is_err());
+        let invalid = KeyDerivation::Argon2id {
+            memory_kib: 1,
+            iterations: 1,
+            parallelism: 1,
+        };
+        assert!(keyring.key_derivation(invalid).is_err());
+    }
+}
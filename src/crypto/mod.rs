@@ -0,0 +1,216 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use magic_crypt::{new_magic_crypt, MagicCryptTrait};
+
+use crate::errors::DecryptError;
+use crate::prelude::EnhancedUnwrap;
+
+mod aead;
+mod encoding;
+mod encrypted;
+mod envelope;
+mod hash;
+pub mod jwt;
+mod kdf;
+mod keyring;
+mod password;
+mod stream;
+
+pub use encoding::{from_base64, from_base64_url, from_hex, to_base64, to_base64_url, to_hex};
+pub use encrypted::{with_keyring, Encrypted};
+pub use envelope::{decrypt_envelope, encrypt_envelope, KeyFuture, KeyProvider, LocalKeyProvider};
+pub use hash::{constant_time_eq, HashAlgorithm, Hasher};
+pub use kdf::KeyDerivation;
+pub use keyring::Keyring;
+pub use password::{
+    hash_password, hash_password_with, needs_rehash, needs_rehash_with, verify_password,
+};
+pub use stream::{decrypt_file, decrypt_stream, encrypt_file, encrypt_stream, CHUNK_SIZE};
+pub(crate) use stream::{stream_key_id, STREAM_HEADER_MAX};
+
+/// return encrypted string in base64, authenticated with AES-256-GCM under a random nonce
+/// and a key derived from `key` with [`KeyDerivation::default`]
+pub fn encrypt_by_key(value: String, key: &str) -> String {
+    Keyring::new("", key).encrypt(&value)
+}
+
+/// Like [`encrypt_by_key`] with the given key derivation, which is recorded in the ciphertext.
+/// Fails for parameters [`KeyDerivation::validate`] rejects.
+pub fn encrypt_by_key_with(
+    value: String,
+    key: &str,
+    kdf: &KeyDerivation,
+) -> Result<String, DecryptError> {
+    Ok(Keyring::new("", key).key_derivation(*kdf)?.encrypt(&value))
+}
+
+/// Encrypt raw bytes like [`encrypt_by_key`], without base64
+pub fn encrypt_bytes(data: &[u8], key: &str) -> Vec<u8> {
+    Keyring::new("", key).encrypt_bytes(data)
+}
+
+pub fn decrypt_bytes(data: &[u8], key: &str) -> Result<Vec<u8>, DecryptError> {
+    Keyring::new("", key).decrypt_bytes(data)
+}
+
+/// return decrypted string from base64
+pub fn decrypt_by_key(value: String, key: &str) -> String {
+    decrypt_by_key_with_error(value, key).unwp()
+}
+
+/// return decrypted string from base64, if error, return default
+pub fn decrypt_by_key_with_default(value: String, key: &str, default: &str) -> String {
+    match decrypt_by_key_with_error(value, key) {
+        Ok(decrypted) => decrypted,
+        Err(_) => default.to_string(),
+    }
+}
+
+/// return decrypted result from base64, if error, return Err.
+/// The key is derived again with the parameters stored in the ciphertext.
+/// Values written by magic-crypt before the versioned format are still read, see [`is_legacy`].
+pub fn decrypt_by_key_with_error(value: String, key: &str) -> Result<String, DecryptError> {
+    Keyring::new("", key).decrypt(&value)
+}
+
+/// Whether `value` was encrypted by magic-crypt (AES-256-CBC without authentication).
+/// Such values should be migrated by decrypting and encrypting them again.
+pub fn is_legacy(value: &str) -> bool {
+    !STANDARD
+        .decode(value.trim())
+        .is_ok_and(|data| aead::is_sealed(&data))
+}
+
+pub(crate) fn decrypt_legacy(value: String, key: &str) -> Result<String, DecryptError> {
+    let mc = new_magic_crypt!(key, 256);
+    mc.decrypt_base64_to_string(value)
+        .map_err(|e| DecryptError {
+            details: format!("{}", e),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use magic_crypt::{new_magic_crypt, MagicCryptTrait};
+
+    use crate::crypto::{decrypt_by_key_with_error, encrypt_by_key_with, KeyDerivation};
+
+    #[test]
+    fn encrypt_test() {
+        let msg = "https?";
+        let key = "foo";
+        let encrypted = crate::crypto::encrypt_by_key(msg.to_string(), key);
+        let decrypted = crate::crypto::decrypt_by_key(encrypted, key);
+
+        assert_eq!(msg, decrypted);
+    }
+
+    #[test]
+    fn decrypt_test() {
+        let msg = "https?";
+        let key = "foo";
+        let default = "default msg";
+        let decrypted = crate::crypto::decrypt_by_key_with_default(msg.to_string(), key, default);
+        assert_eq!(decrypted, default);
+
+        let result = std::panic::catch_unwind(|| {
+            if crate::crypto::decrypt_by_key_with_error(msg.to_string(), key).is_ok() {
+                panic!("should be error");
+            }
+            let encrypted = crate::crypto::encrypt_by_key(msg.to_string(), key);
+            if let Ok(decrypted) = crate::crypto::decrypt_by_key_with_error(encrypted, key) {
+                assert_eq!(msg, decrypted);
+            } else {
+                panic!("decrypt error");
+            }
+        });
+        if let Err(err) = result {
+            eprintln!("Got an error: {:?}", err);
+            panic!("Decrypt error: {:?}", err);
+        }
+    }
+
+    #[test]
+    fn authenticated_test() {
+        let key = "foo";
+        let encrypted = crate::crypto::encrypt_by_key("secret".to_string(), key);
+        assert_ne!(
+            encrypted,
+            crate::crypto::encrypt_by_key("secret".to_string(), key)
+        );
+        assert!(!crate::crypto::is_legacy(&encrypted));
+        assert!(crate::crypto::decrypt_by_key_with_error(encrypted.clone(), "bar").is_err());
+
+        let mut data = STANDARD.decode(&encrypted).unwrap();
+        for i in [4, 10, data.len() - 1] {
+            data[i] ^= 1;
+            let tampered = STANDARD.encode(&data);
+            assert!(crate::crypto::decrypt_by_key_with_error(tampered, key).is_err());
+            data[i] ^= 1;
+        }
+
+        let legacy = new_magic_crypt!(key, 256).encrypt_str_to_base64("secret");
+        assert!(crate::crypto::is_legacy(&legacy));
+        assert_eq!(crate::crypto::decrypt_by_key(legacy, key), "secret");
+    }
+
+    #[test]
+    fn key_derivation_test() {
+        let fast_argon2 = KeyDerivation::Argon2id {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let fast_pbkdf2 = KeyDerivation::Pbkdf2 { iterations: 1000 };
+        for kdf in [fast_argon2, fast_pbkdf2, KeyDerivation::Sha256] {
+            let encrypted = encrypt_by_key_with("secret".to_string(), "foo", &kdf).unwrap();
+            assert_eq!(
+                decrypt_by_key_with_error(encrypted.clone(), "foo").unwrap(),
+                "secret"
+            );
+            assert!(decrypt_by_key_with_error(encrypted, "bar").is_err());
+        }
+        assert_eq!(
+            fast_pbkdf2.derive("foo", b"0123456789abcdef").unwrap(),
+            fast_pbkdf2.derive("foo", b"0123456789abcdef").unwrap()
+        );
+
+        // every ciphertext has its own salt, derived keys are cached by salt
+        let salt = fast_argon2.new_salt();
+        assert_eq!(salt.len(), 16);
+        assert_ne!(salt, fast_argon2.new_salt());
+        assert!(KeyDerivation::Sha256.new_salt().is_empty());
+        let first = encrypt_by_key_with("secret".to_string(), "foo", &fast_pbkdf2).unwrap();
+        let second = encrypt_by_key_with("secret".to_string(), "foo", &fast_pbkdf2).unwrap();
+        let salt_of = |encrypted: &str| STANDARD.decode(encrypted).unwrap()[12..28].to_vec();
+        assert_ne!(salt_of(&first), salt_of(&second));
+        assert_eq!(
+            fast_argon2.derive_cached("foo", &salt).unwrap(),
+            fast_argon2.derive("foo", &salt).unwrap()
+        );
+        assert_ne!(
+            fast_argon2.derive_cached("bar", &salt).unwrap(),
+            fast_argon2.derive("foo", &salt).unwrap()
+        );
+
+        // the parameters are authenticated and bounded
+        let encrypted = encrypt_by_key_with("secret".to_string(), "foo", &fast_pbkdf2).unwrap();
+        let mut data = STANDARD.decode(encrypted).unwrap();
+        data[11] ^= 1;
+        let tampered = STANDARD.encode(&data);
+        assert!(decrypt_by_key_with_error(tampered, "foo").is_err());
+        data[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        let forged = STANDARD.encode(&data);
+        let err = decrypt_by_key_with_error(forged, "foo").unwrap_err();
+        assert!(err.to_string().contains("exceed"));
+        let costly = KeyDerivation::Argon2id {
+            memory_kib: 256 * 1024 + 1,
+            iterations: 1,
+            parallelism: 1,
+        };
+        assert!(costly.derive("foo", b"0123456789abcdef").is_err());
+        assert!(encrypt_by_key_with("secret".to_string(), "foo", &costly).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use aes_gcm::aead::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+
+use crate::crypto::KeyDerivation;
+use crate::errors::PasswordError;
+
+/// Hash `password` with Argon2id and the parameters of [`KeyDerivation::argon2id`] under a
+/// random salt. The result is a PHC string such as `$argon2id$v=19$m=19456,t=2,p=1$...`
+/// which holds everything needed to verify it.
+pub fn hash_password(password: &str) -> Result<String, PasswordError> {
+    hash_password_with(password, &KeyDerivation::argon2id())
+}
+
+/// Like [`hash_password`] with the given parameters, only [`KeyDerivation::Argon2id`] is
+/// supported
+pub fn hash_password_with(password: &str, kdf: &KeyDerivation) -> Result<String, PasswordError> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = argon2(kdf)?
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| PasswordError {
+            details: format!("failed to hash password: {}", e),
+        })?;
+    Ok(hash.to_string())
+}
+
+/// Whether `password` matches the PHC string `hash`, with the parameters stored in it.
+/// Returns an error only if `hash` cannot be read.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, PasswordError> {
+    let hash = parse(hash)?;
+    match Argon2::default().verify_password(password.as_bytes(), &hash) {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(PasswordError {
+            details: format!("failed to verify password: {}", e),
+        }),
+    }
+}
+
+/// Whether `hash` was made with another algorithm, version or parameters than
+/// [`hash_password`] uses now. Hash the password again after a successful verification.
+pub fn needs_rehash(hash: &str) -> bool {
+    needs_rehash_with(hash, &KeyDerivation::argon2id())
+}
+
+/// Like [`needs_rehash`] comparing against the given parameters
+pub fn needs_rehash_with(hash: &str, kdf: &KeyDerivation) -> bool {
+    let Ok(hash) = parse(hash) else {
+        return true;
+    };
+    let Ok(params) = Params::try_from(&hash) else {
+        return true;
+    };
+    let current = KeyDerivation::Argon2id {
+        memory_kib: params.m_cost(),
+        iterations: params.t_cost(),
+        parallelism: params.p_cost(),
+    };
+    hash.algorithm != Algorithm::Argon2id.ident()
+        || hash.version != Some(Version::V0x13.into())
+        || current != *kdf
+}
+
+fn parse(hash: &str) -> Result<PasswordHash<'_>, PasswordError> {
+    PasswordHash::new(hash.trim()).map_err(|e| PasswordError {
+        details: format!("invalid password hash: {}", e),
+    })
+}
+
+fn argon2(kdf: &KeyDerivation) -> Result<Argon2<'static>, PasswordError> {
+    let KeyDerivation::Argon2id {
+        memory_kib,
+        iterations,
+        parallelism,
+    } = *kdf
+    else {
+        return Err(PasswordError {
+            details: format!("passwords can only be hashed with argon2id, not {:?}", kdf),
+        });
+    };
+    let params =
+        Params::new(memory_kib, iterations, parallelism, None).map_err(|e| PasswordError {
+            details: format!("invalid argon2 parameters: {}", e),
+        })?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::{
+        hash_password, hash_password_with, needs_rehash, needs_rehash_with, verify_password,
+        KeyDerivation,
+    };
+
+    #[test]
+    fn password_hashing() {
+        let hash = hash_password("hunter2").unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+        assert_ne!(hash, hash_password("hunter2").unwrap());
+        assert!(verify_password("hunter2", &hash).unwrap());
+        assert!(!verify_password("hunter3", &hash).unwrap());
+        assert!(!needs_rehash(&hash));
+
+        let fast = KeyDerivation::Argon2id {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let outdated = hash_password_with("hunter2", &fast).unwrap();
+        assert!(verify_password("hunter2", &outdated).unwrap());
+        assert!(needs_rehash(&outdated));
+        assert!(!needs_rehash_with(&outdated, &fast));
+
+        assert!(verify_password("hunter2", "$2b$12$not-a-phc-hash").is_err());
+        assert!(needs_rehash("plain text"));
+        let err = hash_password_with("hunter2", &KeyDerivation::Sha256).unwrap_err();
+        assert!(err.to_string().contains("argon2id"));
+    }
+}
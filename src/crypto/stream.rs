@@ -0,0 +1,453 @@
+//! Chunked AES-256-GCM encryption of streams too large to hold in memory.
+//!
+//! Layout: `MAGIC | version | algorithm | header length | key id | key derivation | salt |
+//! stream salt | nonce prefix | chunk size`, then every chunk of plaintext is sealed separately.
+//! The chunk key is derived with HKDF-SHA256 from the derived key and the random stream salt,
+//! so no two streams share a key even when the key derivation takes no salt. A chunk nonce is
+//! the random prefix followed by the chunk counter and a flag marking the final chunk, and the
+//! header is authenticated with every chunk. Reordered, dropped or appended chunks, as well
+//! as a stream cut at a chunk boundary, fail authentication.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+
+use crate::crypto::aead::{cipher, read_key_fields, write_key_fields, MAGIC, VERSION};
+use crate::crypto::kdf::KEY_LEN;
+use crate::crypto::{HashAlgorithm, Keyring};
+use crate::errors::DecryptError;
+use crate::prelude::EnhancedExpect;
+
+/// Plaintext bytes per chunk
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+const ALG_AES_256_GCM_STREAM: u8 = 2;
+/// chunk sizes accepted when reading a header, so a forged header cannot exhaust memory
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+const STREAM_SALT_LEN: usize = 32;
+/// HKDF info of the chunk key
+const CHUNK_KEY_INFO: &[u8] = b"busylib stream chunk key";
+const PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+/// magic, version, algorithm and header length
+const FIXED_LEN: usize = MAGIC.len() + 4;
+/// Upper bound of the bytes [`stream_key_id`] needs
+pub(crate) const STREAM_HEADER_MAX: usize = FIXED_LEN + u16::MAX as usize;
+
+/// Encrypt everything `reader` yields into `writer` with the primary key of `keyring`,
+/// return the number of plaintext bytes
+pub async fn encrypt_stream<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    keyring: &Keyring,
+) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let id = keyring.primary_id().to_string();
+    let key = keyring
+        .key(&id)
+        .ex("the primary key is in the keyring")
+        .to_string();
+    let kdf = *keyring.kdf();
+    // key derivation takes long enough to stall the runtime
+    let (mut header, key) = tokio::task::spawn_blocking(move || {
+        let mut header = Vec::with_capacity(96 + id.len());
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&[VERSION, ALG_AES_256_GCM_STREAM, 0, 0]);
+        let key = write_key_fields(&mut header, &id, &key, &kdf);
+        (header, key)
+    })
+    .await
+    .map_err(io::Error::other)?;
+    let mut salt = [0u8; STREAM_SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    header.extend_from_slice(&salt);
+    let cipher = cipher(&chunk_key(&key, &salt));
+    let mut prefix = [0u8; PREFIX_LEN];
+    OsRng.fill_bytes(&mut prefix);
+    header.extend_from_slice(&prefix);
+    header.extend_from_slice(&(CHUNK_SIZE as u32).to_be_bytes());
+    let len = u16::try_from(header.len() - FIXED_LEN).map_err(invalid_input)?;
+    header[FIXED_LEN - 2..FIXED_LEN].copy_from_slice(&len.to_be_bytes());
+    writer.write_all(&header).await?;
+
+    let mut buffer = Vec::with_capacity(CHUNK_SIZE + 1);
+    let mut total = 0u64;
+    let mut counter = 0u32;
+    loop {
+        fill(reader, &mut buffer, CHUNK_SIZE + 1).await?;
+        let last = buffer.len() <= CHUNK_SIZE;
+        let size = buffer.len().min(CHUNK_SIZE);
+        let chunk = cipher
+            .encrypt(
+                &nonce(&prefix, counter, last),
+                Payload {
+                    msg: &buffer[..size],
+                    aad: &header,
+                },
+            )
+            .map_err(invalid_input)?;
+        writer.write_all(&chunk).await?;
+        total += size as u64;
+        buffer.drain(..size);
+        if last {
+            break;
+        }
+        counter = counter
+            .checked_add(1)
+            .ok_or_else(|| invalid_input("stream exceeds the maximum number of chunks"))?;
+    }
+    writer.flush().await?;
+    Ok(total)
+}
+
+/// Decrypt a stream written by [`encrypt_stream`] with the matching key of `keyring`,
+/// return the number of plaintext bytes.
+///
+/// Every chunk is authenticated before it is written, but on error the chunks already
+/// written must be discarded as the stream is incomplete, see [`decrypt_file`].
+/// Errors caused by the ciphertext have the kind [`io::ErrorKind::InvalidData`] and wrap
+/// a [`DecryptError`].
+pub async fn decrypt_stream<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    keyring: &Keyring,
+) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut header = vec![0u8; FIXED_LEN];
+    reader
+        .read_exact(&mut header)
+        .await
+        .map_err(|_| invalid_data("ciphertext is truncated"))?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(invalid_data("not an encrypted stream"));
+    }
+    let (version, alg) = (header[MAGIC.len()], header[MAGIC.len() + 1]);
+    if version != VERSION || alg != ALG_AES_256_GCM_STREAM {
+        return Err(invalid_data(format!(
+            "unsupported stream version {} or algorithm {}",
+            version, alg
+        )));
+    }
+    let len = u16::from_be_bytes([header[FIXED_LEN - 2], header[FIXED_LEN - 1]]);
+    header.resize(FIXED_LEN + usize::from(len), 0);
+    reader
+        .read_exact(&mut header[FIXED_LEN..])
+        .await
+        .map_err(|_| invalid_data("ciphertext is truncated"))?;
+
+    let (fields, rest) = read_key_fields(&header[FIXED_LEN..]).map_err(decrypt_error)?;
+    if rest.len() != STREAM_SALT_LEN + PREFIX_LEN + 4 {
+        return Err(invalid_data("invalid stream header"));
+    }
+    let (salt, rest) = rest.split_at(STREAM_SALT_LEN);
+    let (prefix, size) = rest.split_at(PREFIX_LEN);
+    let chunk_size = u32::from_be_bytes(size.try_into().unwrap_or_default()) as usize;
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(invalid_data(format!("invalid chunk size {}", chunk_size)));
+    }
+    let key = keyring
+        .key(fields.key_id)
+        .ok_or_else(|| invalid_data(format!("unknown key id `{}`", fields.key_id)))?
+        .to_string();
+    let (kdf, kdf_salt) = (fields.kdf, fields.salt.to_vec());
+    let key = tokio::task::spawn_blocking(move || kdf.derive_cached(&key, &kdf_salt))
+        .await
+        .map_err(io::Error::other)?
+        .map_err(decrypt_error)?;
+    let cipher = cipher(&chunk_key(&key, salt));
+
+    let sealed_size = chunk_size + TAG_LEN;
+    let mut buffer = Vec::with_capacity(sealed_size + 1);
+    let mut total = 0u64;
+    let mut counter = 0u32;
+    loop {
+        fill(reader, &mut buffer, sealed_size + 1).await?;
+        if buffer.len() < TAG_LEN {
+            return Err(invalid_data("ciphertext is truncated"));
+        }
+        let last = buffer.len() <= sealed_size;
+        let size = buffer.len().min(sealed_size);
+        let chunk = open(&cipher, prefix, counter, last, &buffer[..size], &header)?;
+        writer.write_all(&chunk).await?;
+        total += chunk.len() as u64;
+        buffer.drain(..size);
+        if last {
+            break;
+        }
+        counter = counter
+            .checked_add(1)
+            .ok_or_else(|| invalid_data("stream exceeds the maximum number of chunks"))?;
+    }
+    writer.flush().await?;
+    Ok(total)
+}
+
+/// The id of the key a stream was encrypted with, read from its first bytes. `header` should
+/// hold the first [`STREAM_HEADER_MAX`] bytes of the stream, or all of it if shorter.
+pub(crate) fn stream_key_id(header: &[u8]) -> Option<String> {
+    let valid = header.len() > FIXED_LEN
+        && &header[..MAGIC.len()] == MAGIC
+        && header[MAGIC.len()] == VERSION
+        && header[MAGIC.len() + 1] == ALG_AES_256_GCM_STREAM;
+    if !valid {
+        return None;
+    }
+    let (fields, _) = read_key_fields(&header[FIXED_LEN..]).ok()?;
+    Some(fields.key_id.to_string())
+}
+
+/// Encrypt the file `src` into `dst` with [`encrypt_stream`], `dst` only appears once complete
+pub async fn encrypt_file(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    keyring: &Keyring,
+) -> io::Result<u64> {
+    let mut reader = BufReader::new(File::open(src).await?);
+    let tmp = tmp_path(dst.as_ref());
+    let mut writer = BufWriter::new(File::create(&tmp).await?);
+    let result = encrypt_stream(&mut reader, &mut writer, keyring).await;
+    commit(result, &tmp, dst.as_ref()).await
+}
+
+/// Decrypt the file `src` into `dst` with [`decrypt_stream`], `dst` only appears if the whole
+/// file was authenticated
+pub async fn decrypt_file(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    keyring: &Keyring,
+) -> io::Result<u64> {
+    let mut reader = BufReader::new(File::open(src).await?);
+    let tmp = tmp_path(dst.as_ref());
+    let mut writer = BufWriter::new(File::create(&tmp).await?);
+    let result = decrypt_stream(&mut reader, &mut writer, keyring).await;
+    commit(result, &tmp, dst.as_ref()).await
+}
+
+fn tmp_path(dst: &Path) -> PathBuf {
+    let mut name = dst.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    dst.with_file_name(name)
+}
+
+/// rename the temporary file to `dst` if it was written completely, remove it otherwise
+async fn commit(result: io::Result<u64>, tmp: &Path, dst: &Path) -> io::Result<u64> {
+    match result {
+        Ok(total) => {
+            fs::rename(tmp, dst).await?;
+            Ok(total)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(tmp).await;
+            Err(e)
+        }
+    }
+}
+
+/// the key of the chunks of a stream, derived from the derived key and the stream salt
+fn chunk_key(key: &[u8], salt: &[u8]) -> [u8; KEY_LEN] {
+    hkdf_sha256(key, salt, CHUNK_KEY_INFO)
+}
+
+/// HKDF-SHA256 (RFC 5869) with a single block of output
+fn hkdf_sha256(ikm: &[u8], salt: &[u8], info: &[u8]) -> [u8; KEY_LEN] {
+    let prk = HashAlgorithm::Sha256.hmac(salt, ikm);
+    let okm = HashAlgorithm::Sha256.hmac(&prk, &[info, &[1]].concat());
+    let mut key = [0u8; KEY_LEN];
+    key.copy_from_slice(&okm[..KEY_LEN]);
+    key
+}
+
+/// read until `buffer` holds `target` bytes or the reader is exhausted
+async fn fill<R>(reader: &mut R, buffer: &mut Vec<u8>, target: usize) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    while buffer.len() < target {
+        let start = buffer.len();
+        buffer.resize(target, 0);
+        let read = reader.read(&mut buffer[start..]).await?;
+        buffer.truncate(start + read);
+        if read == 0 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn nonce(
+    prefix: &[u8],
+    counter: u32,
+    last: bool,
+) -> Nonce<<Aes256Gcm as aes_gcm::AeadCore>::NonceSize> {
+    let mut nonce = [0u8; 12];
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..PREFIX_LEN + 4].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = u8::from(last);
+    nonce.into()
+}
+
+fn open(
+    cipher: &Aes256Gcm,
+    prefix: &[u8],
+    counter: u32,
+    last: bool,
+    chunk: &[u8],
+    header: &[u8],
+) -> io::Result<Vec<u8>> {
+    cipher
+        .decrypt(
+            &nonce(prefix, counter, last),
+            Payload {
+                msg: chunk,
+                aad: header,
+            },
+        )
+        .map_err(|_| {
+            invalid_data(format!(
+                "authentication of chunk {} failed, wrong key, tampered or truncated stream",
+                counter
+            ))
+        })
+}
+
+fn invalid_data(details: impl Into<String>) -> io::Error {
+    decrypt_error(DecryptError {
+        details: details.into(),
+    })
+}
+
+fn decrypt_error(error: DecryptError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn invalid_input(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::io::ErrorKind;
+
+    use crate::crypto::aead::read_key_fields;
+    use crate::crypto::stream::{chunk_key, hkdf_sha256, FIXED_LEN, STREAM_SALT_LEN};
+    use crate::crypto::{
+        decrypt_bytes, decrypt_file, decrypt_stream, encrypt_bytes, encrypt_file, encrypt_stream,
+        stream_key_id, KeyDerivation, Keyring, CHUNK_SIZE,
+    };
+
+    fn keyring() -> Keyring {
+        Keyring::new("backup", "foo")
+            .key_derivation(KeyDerivation::Pbkdf2 { iterations: 1000 })
+            .unwrap()
+    }
+
+    async fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let mut encrypted = Vec::new();
+        let read = encrypt_stream(&mut &data[..], &mut encrypted, &keyring())
+            .await
+            .unwrap();
+        assert_eq!(read, data.len() as u64);
+        encrypted
+    }
+
+    #[tokio::test]
+    async fn stream_roundtrip() {
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| (i % 251) as u8).collect();
+        for len in [0, 1, CHUNK_SIZE, data.len()] {
+            let encrypted = roundtrip(&data[..len]).await;
+            let mut decrypted = Vec::new();
+            decrypt_stream(&mut &encrypted[..], &mut decrypted, &keyring())
+                .await
+                .unwrap();
+            assert_eq!(decrypted, &data[..len]);
+        }
+
+        let encrypted = roundtrip(&data).await;
+        let header = encrypted.len() - data.len() - 3 * 16;
+        let fails = |ciphertext: Vec<u8>| async move {
+            let mut decrypted = Vec::new();
+            decrypt_stream(&mut &ciphertext[..], &mut decrypted, &keyring())
+                .await
+                .unwrap_err()
+                .kind()
+        };
+        // cut at a chunk boundary, cut inside a chunk, tampered, with trailing data
+        let boundary = header + 2 * (CHUNK_SIZE + 16);
+        assert_eq!(
+            fails(encrypted[..boundary].to_vec()).await,
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            fails(encrypted[..boundary + 10].to_vec()).await,
+            ErrorKind::InvalidData
+        );
+        let mut tampered = encrypted.clone();
+        tampered[header + 5] ^= 1;
+        assert_eq!(fails(tampered).await, ErrorKind::InvalidData);
+        let mut appended = encrypted.clone();
+        appended.extend_from_slice(&[0; 32]);
+        assert_eq!(fails(appended).await, ErrorKind::InvalidData);
+
+        assert_eq!(stream_key_id(&encrypted).as_deref(), Some("backup"));
+        assert_eq!(stream_key_id(&encrypted[..10]), None);
+
+        let bytes = encrypt_bytes(b"\x00\xffbinary", "foo");
+        assert_eq!(decrypt_bytes(&bytes, "foo").unwrap(), b"\x00\xffbinary");
+    }
+
+    #[tokio::test]
+    async fn chunk_key_per_stream() {
+        // without a salt the derived key is the same for every stream
+        let keyring = Keyring::new("backup", "foo")
+            .key_derivation(KeyDerivation::Sha256)
+            .unwrap();
+        let mut keys = Vec::new();
+        for _ in 0..2 {
+            let mut encrypted = Vec::new();
+            encrypt_stream(&mut &b"data"[..], &mut encrypted, &keyring)
+                .await
+                .unwrap();
+            let (fields, rest) = read_key_fields(&encrypted[FIXED_LEN..]).unwrap();
+            let key = fields.kdf.derive("foo", fields.salt).unwrap();
+            keys.push(chunk_key(&key, &rest[..STREAM_SALT_LEN]));
+        }
+        assert_ne!(keys[0], keys[1]);
+
+        // RFC 5869 test case 1, truncated to one block
+        let salt: Vec<u8> = (0..=0x0c).collect();
+        let info: Vec<u8> = (0xf0..=0xf9).collect();
+        assert_eq!(
+            hex::encode(hkdf_sha256(&[0x0b; 22], &salt, &info)),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf"
+        );
+    }
+
+    #[tokio::test]
+    async fn file_roundtrip() {
+        let dir = env::temp_dir().join("busylib_stream_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (plain, sealed, opened) = (dir.join("plain"), dir.join("sealed"), dir.join("opened"));
+        std::fs::write(&plain, vec![7u8; CHUNK_SIZE + 1]).unwrap();
+
+        encrypt_file(&plain, &sealed, &keyring()).await.unwrap();
+        decrypt_file(&sealed, &opened, &keyring()).await.unwrap();
+        assert_eq!(std::fs::read(&opened).unwrap(), vec![7u8; CHUNK_SIZE + 1]);
+
+        std::fs::remove_file(&opened).unwrap();
+        let wrong = Keyring::new("backup", "bar");
+        assert!(decrypt_file(&sealed, &opened, &wrong).await.is_err());
+        assert!(!opened.exists());
+    }
+}
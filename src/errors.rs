@@ -15,6 +15,144 @@ impl Display for DecryptError {
     }
 }
 
+/// Invalid hex or base64 input
+#[derive(Debug)]
+pub struct DecodeError {
+    pub(crate) details: String,
+}
+
+impl Error for DecodeError {}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+/// Failure to hash a password or to read a stored password hash
+#[derive(Debug)]
+pub struct PasswordError {
+    pub(crate) details: String,
+}
+
+impl Error for PasswordError {}
+
+impl Display for PasswordError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+/// Invalid parameters for a random token
+#[derive(Debug)]
+pub struct RandomError {
+    pub(crate) details: String,
+}
+
+impl Error for RandomError {}
+
+impl Display for RandomError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+/// Why a JSON Web Token was rejected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JwtErrorKind {
+    /// `exp` has passed, beyond the leeway
+    Expired,
+    /// `nbf` is still in the future, beyond the leeway
+    NotYetValid,
+    /// the signature or the algorithm does not match the key
+    InvalidSignature,
+    /// `aud` or `iss` does not match, or a required claim is missing
+    InvalidClaims,
+    /// not a well-formed token, or claims that do not deserialize
+    Malformed,
+    /// the key cannot be used, or signing was attempted without a private key
+    InvalidKey,
+}
+
+#[derive(Debug)]
+pub struct JwtError {
+    pub(crate) kind: JwtErrorKind,
+    pub(crate) details: String,
+}
+
+impl JwtError {
+    pub fn kind(&self) -> JwtErrorKind {
+        self.kind
+    }
+}
+
+impl Error for JwtError {}
+
+impl Display for JwtError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for JwtError {
+    fn from(error: jsonwebtoken::errors::Error) -> Self {
+        use jsonwebtoken::errors::ErrorKind;
+
+        let kind = match error.kind() {
+            ErrorKind::ExpiredSignature => JwtErrorKind::Expired,
+            ErrorKind::ImmatureSignature => JwtErrorKind::NotYetValid,
+            ErrorKind::InvalidSignature | ErrorKind::InvalidAlgorithm => {
+                JwtErrorKind::InvalidSignature
+            }
+            ErrorKind::InvalidAudience
+            | ErrorKind::InvalidIssuer
+            | ErrorKind::InvalidSubject
+            | ErrorKind::MissingRequiredClaim(_) => JwtErrorKind::InvalidClaims,
+            ErrorKind::InvalidEcdsaKey
+            | ErrorKind::InvalidRsaKey(_)
+            | ErrorKind::InvalidKeyFormat
+            | ErrorKind::RsaFailedSigning
+            | ErrorKind::Crypto(_) => JwtErrorKind::InvalidKey,
+            _ => JwtErrorKind::Malformed,
+        };
+        Self {
+            kind,
+            details: error.to_string(),
+        }
+    }
+}
+
+/// Failure of a [`crate::crypto::KeyProvider`] to wrap or unwrap a data key
+#[derive(Debug)]
+pub struct KeyProviderError {
+    pub(crate) details: String,
+}
+
+impl KeyProviderError {
+    /// For providers implemented outside this crate
+    pub fn new(details: impl Into<String>) -> Self {
+        Self {
+            details: details.into(),
+        }
+    }
+}
+
+impl Error for KeyProviderError {}
+
+impl Display for KeyProviderError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl From<KeyProviderError> for DecryptError {
+    fn from(error: KeyProviderError) -> Self {
+        Self {
+            details: format!("failed to unwrap the data key: {}", error),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct RemoveFilesError {
     pub(crate) details: String,
@@ -35,3 +173,117 @@ impl From<tokio_cron_scheduler::JobSchedulerError> for RemoveFilesError {
         }
     }
 }
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub(crate) details: String,
+}
+
+impl Error for ConfigError {}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+#[derive(Debug)]
+pub enum EnvIssueKind {
+    Missing,
+    /// `secret` is set when the value was decrypted or read from a `_FILE`, it is then masked
+    Unparsable {
+        value: String,
+        reason: String,
+        secret: bool,
+    },
+    Undecryptable(DecryptError),
+}
+
+/// A single missing or unparsable environment variable
+#[derive(Debug)]
+pub struct EnvIssue {
+    pub name: String,
+    /// name of the type the value was parsed into
+    pub expected: String,
+    pub kind: EnvIssueKind,
+}
+
+impl Display for EnvIssue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.kind {
+            EnvIssueKind::Missing => {
+                write!(f, "{} is missing, expected {}", self.name, self.expected)
+            }
+            EnvIssueKind::Unparsable {
+                value,
+                reason,
+                secret,
+            } => write!(
+                f,
+                "{}={:?} cannot be parsed as {}: {}",
+                self.name,
+                crate::config::dump::mask(&self.name, value, *secret),
+                self.expected,
+                reason
+            ),
+            EnvIssueKind::Undecryptable(e) => write!(f, "{} cannot be decrypted: {}", self.name, e),
+        }
+    }
+}
+
+/// Every problem found while reading a batch of environment variables
+#[derive(Debug)]
+pub struct EnvError {
+    pub(crate) issues: Vec<EnvIssue>,
+}
+
+impl EnvError {
+    pub fn issues(&self) -> &[EnvIssue] {
+        &self.issues
+    }
+}
+
+impl Error for EnvError {}
+
+impl Display for EnvError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} invalid environment variable(s): ", self.issues.len())?;
+        for (i, issue) in self.issues.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Failure to decrypt the `ENC(...)` value of a configuration variable
+#[derive(Debug)]
+pub struct EnvDecryptError {
+    pub(crate) name: String,
+    pub(crate) source: DecryptError,
+}
+
+impl EnvDecryptError {
+    /// name of the variable holding the encrypted value
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn into_inner(self) -> DecryptError {
+        self.source
+    }
+}
+
+impl Error for EnvDecryptError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl Display for EnvDecryptError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "failed to decrypt `{}`: {}", self.name, self.source)
+    }
+}
@@ -0,0 +1,340 @@
+//! Feature flags with allow/deny lists and deterministic percentage rollouts.
+//!
+//! Flags are declared with defaults, then [`FlagRegistry::load`] applies an optional TOML/JSON
+//! file and `FLAG_<NAME>` variables on top and swaps in a new snapshot. Evaluation reads the
+//! current snapshot without locking.
+//!
+//! ```rust,ignore
+//! flags::FLAGS.declare(Flag::new("new_checkout", false).rollout(10));
+//! flags::FLAGS.load(Some(Path::new("flags.toml")))?;
+//! if flags::is_enabled_for("new_checkout", &tenant_id) {
+//!     // ...
+//! }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::config::loader::read_file;
+use crate::config::lookup;
+use crate::config::watch::poll_files;
+use crate::errors::ConfigError;
+
+pub type FlagSet = BTreeMap<String, Flag>;
+
+/// The process-wide registry used by [`is_enabled`] and [`is_enabled_for`]
+pub static FLAGS: Lazy<FlagRegistry> = Lazy::new(FlagRegistry::new);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Flag {
+    pub name: String,
+    /// state for keys not covered by `rollout`, `allow` or `deny`
+    pub enabled: bool,
+    /// percentage of keys the flag is enabled for, replaces `enabled` for keyed evaluation
+    pub rollout: Option<u8>,
+    pub allow: BTreeSet<String>,
+    pub deny: BTreeSet<String>,
+}
+
+impl Flag {
+    pub fn new(name: &str, enabled: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            enabled,
+            rollout: None,
+            allow: BTreeSet::new(),
+            deny: BTreeSet::new(),
+        }
+    }
+
+    /// Enable the flag for `percent` of the keys, panics if it exceeds 100
+    pub fn rollout(mut self, percent: u8) -> Self {
+        assert!(
+            percent <= 100,
+            "rollout of flag `{}` exceeds 100%",
+            self.name
+        );
+        self.rollout = Some(percent);
+        self
+    }
+
+    pub fn allow(mut self, keys: &[&str]) -> Self {
+        self.allow.extend(keys.iter().map(|k| k.to_string()));
+        self
+    }
+
+    pub fn deny(mut self, keys: &[&str]) -> Self {
+        self.deny.extend(keys.iter().map(|k| k.to_string()));
+        self
+    }
+
+    /// Evaluate without a key: a rollout only counts once it reaches 100%
+    pub fn is_enabled(&self) -> bool {
+        match self.rollout {
+            Some(percent) => percent >= 100,
+            None => self.enabled,
+        }
+    }
+
+    /// Evaluate for `key` (user id, tenant...): deny list, allow list, rollout, then `enabled`.
+    /// The same key always lands in the same rollout bucket, and raising the percentage only
+    /// adds keys.
+    pub fn is_enabled_for(&self, key: &str) -> bool {
+        if self.deny.contains(key) {
+            return false;
+        }
+        if self.allow.contains(key) {
+            return true;
+        }
+        match self.rollout {
+            Some(percent) => bucket(&self.name, key) < u64::from(percent),
+            None => self.enabled,
+        }
+    }
+}
+
+/// partial flag read from a file, unset fields keep the declared value
+#[derive(Deserialize)]
+struct FlagPatch {
+    enabled: Option<bool>,
+    rollout: Option<u8>,
+    allow: Option<BTreeSet<String>>,
+    deny: Option<BTreeSet<String>>,
+}
+
+pub struct FlagRegistry {
+    declared: Mutex<FlagSet>,
+    current: ArcSwap<FlagSet>,
+}
+
+impl Default for FlagRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlagRegistry {
+    pub fn new() -> Self {
+        Self {
+            declared: Mutex::new(FlagSet::new()),
+            current: ArcSwap::from_pointee(FlagSet::new()),
+        }
+    }
+
+    /// Declare a flag with its defaults, it is live immediately unless already loaded.
+    /// Panics if its rollout exceeds 100%.
+    pub fn declare(&self, flag: Flag) {
+        assert!(
+            flag.rollout.is_none_or(|percent| percent <= 100),
+            "rollout of flag `{}` exceeds 100%",
+            flag.name
+        );
+        if let Ok(mut declared) = self.declared.lock() {
+            declared.insert(flag.name.clone(), flag.clone());
+        }
+        self.current.rcu(|current| {
+            let mut current = current.as_ref().clone();
+            current.entry(flag.name.clone()).or_insert(flag.clone());
+            current
+        });
+    }
+
+    /// Rebuild the snapshot from the declared defaults, `file` and the env, then swap it in.
+    ///
+    /// A file holds one table per flag with optional `enabled`, `rollout`, `allow` and `deny`
+    /// fields. For every flag `FLAG_<NAME>` may be `on`/`off` or a percentage like `25%`, and
+    /// `FLAG_<NAME>_ALLOW`/`FLAG_<NAME>_DENY` comma separated keys. On error the current
+    /// snapshot is kept.
+    pub fn load(&self, file: Option<&Path>) -> Result<(), ConfigError> {
+        let mut flags = self
+            .declared
+            .lock()
+            .map(|declared| declared.clone())
+            .unwrap_or_default();
+        if let Some(path) = file.filter(|p| p.exists()) {
+            for (name, value) in read_file(path)? {
+                let patch: FlagPatch = serde_json::from_value(value).map_err(|e| ConfigError {
+                    details: format!("invalid flag `{}` in {}: {}", name, path.display(), e),
+                })?;
+                let flag = flags
+                    .entry(name.clone())
+                    .or_insert_with(|| Flag::new(&name, false));
+                flag.enabled = patch.enabled.unwrap_or(flag.enabled);
+                flag.rollout = patch.rollout.or(flag.rollout);
+                flag.allow = patch.allow.unwrap_or(std::mem::take(&mut flag.allow));
+                flag.deny = patch.deny.unwrap_or(std::mem::take(&mut flag.deny));
+            }
+        }
+        for flag in flags.values_mut() {
+            apply_env(flag)?;
+            if flag.rollout.is_some_and(|percent| percent > 100) {
+                return Err(ConfigError {
+                    details: format!("rollout of flag `{}` exceeds 100%", flag.name),
+                });
+            }
+        }
+        self.current.store(Arc::new(flags));
+        Ok(())
+    }
+
+    /// [`FlagRegistry::load`] every time `file` changes
+    pub fn watch(&'static self, file: PathBuf, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let path = file.clone();
+        poll_files(vec![file], interval, move || self.load(Some(&path)))
+    }
+
+    /// Undeclared flags are disabled
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.current
+            .load()
+            .get(name)
+            .is_some_and(|flag| flag.is_enabled())
+    }
+
+    pub fn is_enabled_for(&self, name: &str, key: &str) -> bool {
+        self.current
+            .load()
+            .get(name)
+            .is_some_and(|flag| flag.is_enabled_for(key))
+    }
+
+    pub fn snapshot(&self) -> Arc<FlagSet> {
+        self.current.load_full()
+    }
+}
+
+/// [`FlagRegistry::is_enabled`] on [`FLAGS`]
+pub fn is_enabled(name: &str) -> bool {
+    FLAGS.is_enabled(name)
+}
+
+/// [`FlagRegistry::is_enabled_for`] on [`FLAGS`]
+pub fn is_enabled_for(name: &str, key: &str) -> bool {
+    FLAGS.is_enabled_for(name, key)
+}
+
+fn apply_env(flag: &mut Flag) -> Result<(), ConfigError> {
+    let var = format!("FLAG_{}", flag.name.to_uppercase().replace(['-', '.'], "_"));
+    let read = |name: &str| {
+        lookup(name).map_err(|e| ConfigError {
+            details: e.to_string(),
+        })
+    };
+    if let Some(value) = read(&var)? {
+        let value = value.trim().to_lowercase();
+        match value.as_str() {
+            "on" | "true" | "1" | "yes" => (flag.enabled, flag.rollout) = (true, None),
+            "off" | "false" | "0" | "no" => (flag.enabled, flag.rollout) = (false, None),
+            _ => {
+                let percent = value
+                    .strip_suffix('%')
+                    .and_then(|p| p.trim().parse::<u8>().ok())
+                    .ok_or_else(|| ConfigError {
+                        details: format!("{}={:?} is neither on/off nor a percentage", var, value),
+                    })?;
+                flag.rollout = Some(percent);
+            }
+        }
+    }
+    let keys = |value: String| -> BTreeSet<String> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|k| !k.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    if let Some(value) = read(&format!("{}_ALLOW", var))? {
+        flag.allow = keys(value);
+    }
+    if let Some(value) = read(&format!("{}_DENY", var))? {
+        flag.deny = keys(value);
+    }
+    Ok(())
+}
+
+/// stable bucket in `0..100` of `key` for the flag `name` (FNV-1a)
+fn bucket(name: &str, key: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in name.bytes().chain([b':']).chain(key.bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash % 100
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use crate::config::EnvOverrides;
+    use crate::flags::{bucket, Flag, FlagRegistry};
+
+    #[test]
+    fn evaluate_flag() {
+        let flag = Flag::new("checkout", false)
+            .rollout(30)
+            .allow(&["vip"])
+            .deny(&["blocked"]);
+        assert!(flag.is_enabled_for("vip"));
+        assert!(!flag.is_enabled_for("blocked"));
+        assert!(!flag.is_enabled());
+
+        let enabled = (0..1000)
+            .filter(|i| flag.is_enabled_for(&format!("user-{}", i)))
+            .count();
+        assert!((200..400).contains(&enabled), "{} of 1000 enabled", enabled);
+        // raising the rollout keeps every key enabled so far
+        let wider = flag.clone().rollout(60);
+        assert!((0..1000)
+            .map(|i| format!("user-{}", i))
+            .filter(|key| flag.is_enabled_for(key))
+            .all(|key| wider.is_enabled_for(&key)));
+        assert_eq!(bucket("checkout", "user-1"), bucket("checkout", "user-1"));
+
+        assert!(std::panic::catch_unwind(|| Flag::new("checkout", false).rollout(150)).is_err());
+        let mut invalid = Flag::new("checkout", false);
+        invalid.rollout = Some(150);
+        assert!(std::panic::catch_unwind(|| FlagRegistry::new().declare(invalid)).is_err());
+    }
+
+    #[test]
+    fn load_file_and_env() {
+        let dir = env::temp_dir().join("busylib_flags_test");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("flags.toml");
+        fs::write(
+            &file,
+            "[flags_test_dark_mode]\nenabled = true\n\n[flags_test_search]\nrollout = 100\n",
+        )
+        .unwrap();
+        let _guard = EnvOverrides::new()
+            .set("FLAG_FLAGS_TEST_BETA", "off")
+            .set("FLAG_FLAGS_TEST_BETA_ALLOW", "tenant-1, tenant-2")
+            .enter();
+
+        let registry = FlagRegistry::new();
+        registry.declare(Flag::new("flags_test_dark_mode", false));
+        registry.declare(Flag::new("flags_test_beta", true));
+        assert!(!registry.is_enabled("flags_test_dark_mode"));
+        assert!(registry.is_enabled("flags_test_beta"));
+
+        registry.load(Some(&file)).unwrap();
+        assert!(registry.is_enabled("flags_test_dark_mode"));
+        assert!(registry.is_enabled("flags_test_search"));
+        assert!(!registry.is_enabled("flags_test_beta"));
+        assert!(registry.is_enabled_for("flags_test_beta", "tenant-2"));
+        assert!(!registry.is_enabled("flags_test_unknown"));
+
+        fs::write(&file, "[flags_test_search]\nrollout = 101\n").unwrap();
+        assert!(registry.load(Some(&file)).is_err());
+        assert!(registry.is_enabled("flags_test_dark_mode"));
+    }
+}
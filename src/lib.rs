@@ -3,8 +3,13 @@
 pub mod config;
 pub mod crypto;
 pub mod errors;
+pub mod flags;
 pub mod http;
 pub mod logger;
 pub mod prelude;
+pub mod random;
+
+#[doc(hidden)]
+pub mod __cli;
 
 pub const ANY: &str = "any";
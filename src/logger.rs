@@ -17,6 +17,7 @@ use tracing_subscriber::{
     Layer, Registry,
 };
 
+use crate::config::{active_profile, Profile};
 use crate::errors::RemoveFilesError;
 use crate::prelude::EnhancedExpect;
 
@@ -31,7 +32,8 @@ pub struct LogConfig {
 }
 
 impl LogConfig {
-    /// crates_to_log: start with bin name, following with other crates
+    /// crates_to_log: start with bin name, following with other crates.
+    /// Defaults follow the active profile, see [`LogConfig::profile`]
     pub fn new(crates_to_log: &[&str]) -> Self {
         if crates_to_log.is_empty() {
             panic!(
@@ -44,6 +46,15 @@ impl LogConfig {
             directory: None,
             json_format: false,
         }
+        .profile(&active_profile())
+    }
+
+    /// Apply the defaults of `profile`: `debug` level for dev and test, `info` otherwise
+    pub fn profile(self, profile: &Profile) -> Self {
+        match profile {
+            Profile::Dev | Profile::Test => self.level(log::Level::Debug),
+            _ => self.level(log::Level::Info),
+        }
     }
 
     pub fn level(mut self, level: log::Level) -> Self {
@@ -0,0 +1,143 @@
+//! Cryptographically secure random tokens and identifiers, drawn from the operating system.
+//!
+//! ```rust,ignore
+//! let api_key = format!("sk_{}", random::token_alphanumeric(32));
+//! let request_id = random::ulid().to_string();
+//! ```
+
+use std::sync::Mutex;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use once_cell::sync::Lazy;
+use ulid::Generator;
+
+use crate::crypto::{to_base64_url, to_hex};
+use crate::errors::RandomError;
+use crate::prelude::EnhancedExpect;
+
+pub use ulid::Ulid;
+pub use uuid::Uuid;
+
+/// Letters and digits
+pub const ALPHANUMERIC: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Keeps ULIDs created within the same millisecond increasing
+static ULID_GENERATOR: Lazy<Mutex<Generator>> = Lazy::new(|| Mutex::new(Generator::new()));
+
+/// `len` random bytes
+pub fn bytes(len: usize) -> Vec<u8> {
+    let mut data = vec![0u8; len];
+    OsRng.fill_bytes(&mut data);
+    data
+}
+
+/// `len` random bytes in URL-safe base64 without padding, 32 bytes give 43 characters
+pub fn token_urlsafe(len: usize) -> String {
+    to_base64_url(bytes(len))
+}
+
+/// `len` random bytes in lowercase hex, 32 bytes give 64 characters
+pub fn token_hex(len: usize) -> String {
+    to_hex(bytes(len))
+}
+
+/// `len` characters from [`ALPHANUMERIC`]
+pub fn token_alphanumeric(len: usize) -> String {
+    token_from_alphabet(len, ALPHANUMERIC).ex("ALPHANUMERIC is a valid alphabet")
+}
+
+/// `len` characters drawn uniformly from `alphabet`, which needs between 2 and 256 distinct
+/// characters
+pub fn token_from_alphabet(len: usize, alphabet: &str) -> Result<String, RandomError> {
+    let chars: Vec<char> = alphabet.chars().collect();
+    if chars.len() < 2 || chars.len() > 256 {
+        return Err(RandomError {
+            details: format!(
+                "an alphabet needs between 2 and 256 characters, got {}",
+                chars.len()
+            ),
+        });
+    }
+    if let Some(c) = chars
+        .iter()
+        .enumerate()
+        .find_map(|(i, c)| chars[..i].contains(c).then_some(c))
+    {
+        return Err(RandomError {
+            details: format!("alphabet contains {:?} more than once", c),
+        });
+    }
+    // bytes above the largest multiple of the alphabet size are rejected to avoid modulo bias
+    let limit = 256 - 256 % chars.len();
+    let mut token = String::with_capacity(len);
+    let mut count = 0;
+    while count < len {
+        for byte in bytes(len - count + 8) {
+            if usize::from(byte) < limit && count < len {
+                token.push(chars[usize::from(byte) % chars.len()]);
+                count += 1;
+            }
+        }
+    }
+    Ok(token)
+}
+
+/// A random UUID (version 4)
+pub fn uuid_v4() -> Uuid {
+    Uuid::new_v4()
+}
+
+/// A UUID ordered by creation time (version 7), suited to database keys
+pub fn uuid_v7() -> Uuid {
+    Uuid::now_v7()
+}
+
+/// A ULID, lexicographically sortable by creation time and monotonic within this process
+pub fn ulid() -> Ulid {
+    // the random part only overflows after 2^80 ids in one millisecond
+    if let Ok(mut generator) = ULID_GENERATOR.lock() {
+        if let Ok(id) = generator.generate() {
+            return id;
+        }
+    }
+    Ulid::new()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::random::{
+        bytes, token_alphanumeric, token_from_alphabet, token_hex, token_urlsafe, ulid, uuid_v4,
+        uuid_v7, ALPHANUMERIC,
+    };
+
+    #[test]
+    fn tokens_and_ids() {
+        assert_eq!(bytes(16).len(), 16);
+        assert_ne!(bytes(16), bytes(16));
+        assert_eq!(token_urlsafe(32).len(), 43);
+        assert!(token_urlsafe(32)
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(token_hex(32).len(), 64);
+        let token = token_alphanumeric(40);
+        assert_eq!(token.len(), 40);
+        assert!(token.chars().all(|c| ALPHANUMERIC.contains(c)));
+
+        let pin = token_from_alphabet(6, "0123456789").unwrap();
+        assert_eq!(pin.len(), 6);
+        assert!(pin.chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(token_from_alphabet(5, "αβ").unwrap().chars().count(), 5);
+        assert!(token_from_alphabet(8, "a").is_err());
+        assert!(token_from_alphabet(8, "abca").is_err());
+
+        assert_eq!(uuid_v4().get_version_num(), 4);
+        assert_eq!(uuid_v7().get_version_num(), 7);
+        let ids: Vec<String> = (0..100).map(|_| ulid().to_string()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), 100);
+        assert_eq!(ids[0].len(), 26);
+    }
+}